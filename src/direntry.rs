@@ -18,11 +18,43 @@ impl Default for ObjectType {
     }
 }
 
+/// What kind of filesystem entry a `FileStat` was taken from. Only `DirectoryStat` (dirstat.rs)
+/// can actually tell these apart today, since that's the only stat source that sees
+/// `FILE_ATTRIBUTE_REPARSE_POINT`; `std::fs`-based producers (worktree.rs, fsmonitor.rs) always
+/// report `Regular` here and rely on their own separate `is_symlink`/`ObjectType` checks instead.
+#[derive(PartialEq, Eq, Debug, Clone)]
+pub enum FileKind {
+    Regular,
+    SymLink,
+    // A reparse point that isn't a symlink (e.g. a mount point or a cloud-storage placeholder).
+    // Not meaningfully comparable against anything in the index, so it's tracked only so callers
+    // can tell it apart from a plain file rather than silently misreading it as one.
+    OtherReparsePoint,
+}
+impl Default for FileKind {
+    fn default() -> Self {
+        FileKind::Regular
+    }
+}
+
 #[derive(PartialEq, Eq, Debug, Default, Clone)]
 pub struct FileStat {
     // modified time in nanoseconds since the unix epoch
     pub mtime: u128,
     pub size: u32,
+
+    // The owner-executable bit.  Git only ever records 100644 or 100755 for a regular file, so
+    // this is the only permission bit it (or we) care about.  Always `false` on platforms
+    // without a concept of it, e.g. Windows.
+    pub executable: bool,
+
+    pub kind: FileKind,
+
+    // The link target text, only ever populated for `FileKind::SymLink` (always `None`
+    // otherwise). A symlink's index blob stores this same target text, so comparing it directly
+    // is what actually tells a changed symlink from an unchanged one - comparing `size` the way a
+    // regular file does describes the reparse buffer on disk, not the text git tracks.
+    pub link_target: Option<String>,
 }
 
 /// Represents an git entry in the index or working tree i.e. a file or blob
@@ -34,4 +66,21 @@ pub struct DirEntry {
     // The docs call this "object name"
     pub sha: [u8; 20],
     pub name: String,
+
+    // 0 = normal, 1 = base, 2 = ours, 3 = theirs.  Entries with a non-zero stage are one side of
+    // an unresolved merge conflict rather than the normal single copy of a path.
+    pub stage: u8,
+
+    // git only sets this on the entries it wrote out itself; it's not meaningful to us, but it's
+    // surfaced for completeness.
+    pub assume_valid: bool,
+
+    // The worktree copy of this entry should be treated as unchanged without checking it, e.g.
+    // as used by a sparse checkout.
+    pub skip_worktree: bool,
+
+    // The entry was added with `git add --intent-to-add`: it's in the index with an empty blob,
+    // but `git status` should still report it as a new, untracked-style file rather than as
+    // unmodified.
+    pub intent_to_add: bool,
 }
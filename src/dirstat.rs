@@ -7,20 +7,130 @@
 
 use memoffset::offset_of;
 use ntapi::ntioapi::{
-    FileFullDirectoryInformation, NtQueryDirectoryFile, FILE_FULL_DIR_INFORMATION, IO_STATUS_BLOCK,
+    FileFullDirectoryInformation, NtCreateFile, NtQueryDirectoryFile, FILE_DIRECTORY_FILE,
+    FILE_FULL_DIR_INFORMATION, FILE_OPEN, FILE_OPEN_REPARSE_POINT, FILE_SYNCHRONOUS_IO_NONALERT,
+    IO_STATUS_BLOCK,
 };
+use ntapi::ntobapi::OBJECT_ATTRIBUTES;
+use ntapi::ntrtl::RtlInitUnicodeString;
 use std::collections::HashMap;
+use std::convert::TryInto;
 use std::ffi::CString;
+use std::mem::size_of;
+use std::os::windows::ffi::OsStrExt;
 use std::path::Path;
+use winapi::shared::ntdef::UNICODE_STRING;
 use winapi::um::fileapi::{CreateFileA, OPEN_EXISTING};
 use winapi::um::handleapi::CloseHandle;
+use winapi::um::ioapiset::DeviceIoControl;
 use winapi::um::winbase::FILE_FLAG_BACKUP_SEMANTICS;
+use winapi::um::winioctl::FSCTL_GET_REPARSE_POINT;
 use winapi::um::winnt::{
-    FILE_ATTRIBUTE_DIRECTORY, FILE_LIST_DIRECTORY, FILE_SHARE_DELETE, FILE_SHARE_READ,
-    FILE_SHARE_WRITE, HANDLE, LARGE_INTEGER,
+    FILE_ATTRIBUTE_DIRECTORY, FILE_ATTRIBUTE_REPARSE_POINT, FILE_LIST_DIRECTORY, FILE_SHARE_DELETE,
+    FILE_SHARE_READ, FILE_SHARE_WRITE, GENERIC_READ, HANDLE, IO_REPARSE_TAG_SYMLINK, LARGE_INTEGER,
 };
 
-use crate::direntry::FileStat;
+use crate::direntry::{FileKind, FileStat};
+
+// The on-disk REPARSE_DATA_BUFFER layout Windows fills in for FSCTL_GET_REPARSE_POINT, limited
+// to the symlink-shaped variant (ReparseTag == IO_REPARSE_TAG_SYMLINK); mount points and other
+// reparse tags lay their type-specific data out differently and aren't read here.
+const MAXIMUM_REPARSE_DATA_BUFFER_SIZE: usize = 16 * 1024;
+
+/// An open directory `HANDLE` that closes itself on drop (replaces the old bare-`HANDLE` plus
+/// manual `CloseHandle` call this crate used to make at the end of `get_dir_stats`). Holding the
+/// handle also lets a child directory be opened *relative* to it (see `open_child`), so a
+/// recursive walk only resolves each directory's full path once - from its own parent - instead
+/// of re-resolving every ancestor component on every level the way repeated `CreateFileA` calls
+/// with full paths do, and without the TOCTOU window that re-resolving from scratch opens up if
+/// an ancestor is renamed mid-walk.
+pub struct DirHandle(HANDLE);
+
+// A `DirHandle` is only ever read from or closed, never mutated concurrently, and ownership
+// (along with the exclusive right to use it) moves wholesale into whichever thread recurses into
+// it, so it's safe to send across the `rayon::Scope::spawn` boundary `DirTreeDiff` uses it with.
+unsafe impl Send for DirHandle {}
+
+impl Drop for DirHandle {
+    fn drop(&mut self) {
+        unsafe {
+            CloseHandle(self.0);
+        }
+    }
+}
+
+impl DirHandle {
+    /// Opens `path` from scratch by its full path. This is the only
+    /// entry point that resolves a full path; every directory below it should be reached via
+    /// `open_child` instead.
+    pub fn open(path: &Path) -> DirHandle {
+        let name = CString::new(path.to_str().unwrap()).unwrap();
+        let handle = unsafe {
+            CreateFileA(
+                name.as_ptr(),
+                FILE_LIST_DIRECTORY,
+                FILE_SHARE_WRITE | FILE_SHARE_READ | FILE_SHARE_DELETE,
+                std::ptr::null_mut(),
+                OPEN_EXISTING,
+                FILE_FLAG_BACKUP_SEMANTICS,
+                std::ptr::null_mut(),
+            )
+        };
+        DirHandle(handle)
+    }
+
+    /// Opens the subdirectory `name` of this already-open directory, resolving only that single
+    /// leaf component against `self` as `RootDirectory` rather than walking the full path again -
+    /// the `openat`-style traversal Mercurial's rhg also moved to for the same reason.
+    pub fn open_child(&self, name: &str) -> DirHandle {
+        self.open_relative(name, FILE_DIRECTORY_FILE | FILE_SYNCHRONOUS_IO_NONALERT)
+    }
+
+    /// Like `open_child`, but opens `name` itself rather than following it - the reparse point
+    /// (e.g. a symlink) has to stay unresolved for `FSCTL_GET_REPARSE_POINT` to read its target
+    /// back out, instead of transparently landing on whatever it points to the way a normal open
+    /// would.
+    fn open_child_reparse_point(&self, name: &str) -> DirHandle {
+        self.open_relative(name, FILE_OPEN_REPARSE_POINT | FILE_SYNCHRONOUS_IO_NONALERT)
+    }
+
+    fn open_relative(&self, name: &str, create_options: u32) -> DirHandle {
+        let mut wide_name: Vec<u16> = std::ffi::OsStr::new(name).encode_wide().collect();
+        wide_name.push(0);
+        let mut unicode_name: UNICODE_STRING = unsafe { std::mem::zeroed() };
+        unsafe {
+            RtlInitUnicodeString(&mut unicode_name, wide_name.as_ptr());
+        }
+
+        let mut object_attributes: OBJECT_ATTRIBUTES = unsafe { std::mem::zeroed() };
+        object_attributes.Length = size_of::<OBJECT_ATTRIBUTES>() as u32;
+        object_attributes.RootDirectory = self.0;
+        object_attributes.ObjectName = &mut unicode_name;
+
+        let mut handle: HANDLE = std::ptr::null_mut();
+        let mut io_block: IO_STATUS_BLOCK = unsafe { std::mem::zeroed() };
+        unsafe {
+            NtCreateFile(
+                &mut handle,
+                GENERIC_READ,
+                &mut object_attributes,
+                &mut io_block,
+                std::ptr::null_mut(),
+                0,
+                FILE_SHARE_READ | FILE_SHARE_WRITE | FILE_SHARE_DELETE,
+                FILE_OPEN,
+                create_options,
+                std::ptr::null_mut(),
+                0,
+            );
+        }
+        DirHandle(handle)
+    }
+
+    fn raw(&self) -> HANDLE {
+        self.0
+    }
+}
 
 #[derive(PartialEq, Eq, Debug, Default, Clone)]
 pub struct DirectoryStat {
@@ -35,17 +145,32 @@ impl DirectoryStat {
     ///
     /// * `path` - The path to a directory to get file stats fro
     pub fn new(path: &Path) -> DirectoryStat {
-        let file_stats = DirectoryStat::get_dir_stats(path);
-        let dirstat = DirectoryStat {
+        let handle = DirHandle::open(path);
+        let (file_stats, _sub_dirs) = DirectoryStat::get_dir_stats(&handle);
+        DirectoryStat {
             directory: path.to_str().unwrap().to_string(),
             file_stats,
-        };
-        dirstat
+        }
+    }
+
+    /// Like `new`, but scans through an already-open `handle` instead of resolving `directory`
+    /// from scratch, and also returns the names of any subdirectories found along the way - a
+    /// recursive caller can feed each of those into `handle.open_child` to keep walking down
+    /// without ever re-resolving a full path.
+    pub fn from_handle(handle: &DirHandle, directory: String) -> (DirectoryStat, Vec<String>) {
+        let (file_stats, sub_dirs) = DirectoryStat::get_dir_stats(handle);
+        (
+            DirectoryStat {
+                directory,
+                file_stats,
+            },
+            sub_dirs,
+        )
     }
 
-    fn get_dir_stats(path: &Path) -> HashMap<String, FileStat> {
+    fn get_dir_stats(handle: &DirHandle) -> (HashMap<String, FileStat>, Vec<String>) {
         let mut file_stats = HashMap::new();
-        let handle = DirectoryStat::get_directory_handle(path);
+        let mut sub_dirs = vec![];
         let mut io_block: IO_STATUS_BLOCK = unsafe { std::mem::zeroed() };
         let io_ptr: *mut IO_STATUS_BLOCK = &mut io_block as *mut _;
         let mut buffer: [u8; 1000] = [0; 1000];
@@ -54,7 +179,7 @@ impl DirectoryStat {
             let mut offset = 0;
             let result = unsafe {
                 NtQueryDirectoryFile(
-                    handle,
+                    handle.raw(),
                     std::ptr::null_mut(),
                     None,
                     std::ptr::null_mut(),
@@ -77,54 +202,115 @@ impl DirectoryStat {
                 let file_info = &body[0];
                 let name_offset = name_member_offset + offset;
                 offset += file_info.NextEntryOffset as usize;
+                let name = DirectoryStat::read_string(
+                    &buffer[name_offset..],
+                    file_info.FileNameLength as usize,
+                )
+                .unwrap();
                 if file_info.FileAttributes & FILE_ATTRIBUTE_DIRECTORY == 0 {
                     let mtime = DirectoryStat::windows_time_to_git_time(file_info.LastWriteTime);
                     let size = unsafe { *file_info.EndOfFile.QuadPart() as u32 };
 
-                    let name = DirectoryStat::read_string(
-                        &buffer[name_offset..],
-                        file_info.FileNameLength as usize,
-                    )
-                    .unwrap();
-                    file_stats.insert(name, FileStat { mtime, size });
+                    let (kind, link_target) =
+                        if file_info.FileAttributes & FILE_ATTRIBUTE_REPARSE_POINT == 0 {
+                            (FileKind::Regular, None)
+                        } else if file_info.EaSize == IO_REPARSE_TAG_SYMLINK {
+                            // For the `FileFullDirectoryInformation` class, a reparse point's
+                            // `EaSize` field is reinterpreted by the OS as its 32-bit reparse
+                            // tag instead of an actual EA size - reading it here classifies the
+                            // entry without a second round trip per reparse point.
+                            let target = DirectoryStat::read_symlink_target(handle, &name);
+                            (FileKind::SymLink, target)
+                        } else {
+                            (FileKind::OtherReparsePoint, None)
+                        };
+
+                    file_stats.insert(
+                        name,
+                        FileStat {
+                            mtime,
+                            size,
+                            executable: false,
+                            kind,
+                            link_target,
+                        },
+                    );
+                } else if name != "." && name != ".." {
+                    sub_dirs.push(name);
                 }
                 if file_info.NextEntryOffset == 0 {
                     break;
                 }
             }
         }
-        // TODO look at making a wrapper object and use drop.
-        unsafe {
-            CloseHandle(handle);
-        }
-        file_stats
+        (file_stats, sub_dirs)
     }
 
-    fn get_directory_handle(path: &Path) -> HANDLE {
-        let name = CString::new(path.to_str().unwrap()).unwrap();
-        unsafe {
-            CreateFileA(
-                name.as_ptr(),
-                FILE_LIST_DIRECTORY,
-                FILE_SHARE_WRITE | FILE_SHARE_READ | FILE_SHARE_DELETE,
+    fn read_string(slice: &[u8], size: usize) -> Option<String> {
+        let (_front, slice, _back) = unsafe { slice.align_to::<u16>() };
+        String::from_utf16(&slice[..size / 2]).ok()
+    }
+
+    // Opens `name` (a child of the already-open `handle`) without following it, and reads its
+    // link target text back out via `FSCTL_GET_REPARSE_POINT` - the text git's own symlink blob
+    // stores, and so the thing that actually has to be compared to tell a changed symlink from
+    // an unchanged one, rather than the on-disk size of the reparse buffer.
+    fn read_symlink_target(handle: &DirHandle, name: &str) -> Option<String> {
+        let link_handle = handle.open_child_reparse_point(name);
+        if link_handle.raw().is_null() {
+            return None;
+        }
+
+        let mut buffer = [0u8; MAXIMUM_REPARSE_DATA_BUFFER_SIZE];
+        let mut bytes_returned: u32 = 0;
+        let ok = unsafe {
+            DeviceIoControl(
+                link_handle.raw(),
+                FSCTL_GET_REPARSE_POINT,
                 std::ptr::null_mut(),
-                OPEN_EXISTING,
-                FILE_FLAG_BACKUP_SEMANTICS,
+                0,
+                buffer.as_mut_ptr() as *mut winapi::ctypes::c_void,
+                buffer.len() as u32,
+                &mut bytes_returned,
                 std::ptr::null_mut(),
             )
+        };
+        if ok == 0 {
+            return None;
         }
+
+        DirectoryStat::parse_symlink_reparse_buffer(&buffer[..bytes_returned as usize])
     }
 
-    fn read_string(slice: &[u8], size: usize) -> Option<String> {
-        let (_front, slice, _back) = unsafe { slice.align_to::<u16>() };
-        String::from_utf16(&slice[..size / 2]).ok()
+    // Layout of the symlink-shaped REPARSE_DATA_BUFFER (the caller already confirmed the tag is
+    // IO_REPARSE_TAG_SYMLINK via EaSize before reaching here):
+    //   ReparseTag: u32, ReparseDataLength: u16, Reserved: u16,
+    //   SubstituteNameOffset: u16, SubstituteNameLength: u16,
+    //   PrintNameOffset: u16, PrintNameLength: u16, Flags: u32,
+    //   PathBuffer: [u16] (SubstituteName and PrintName, both relative to the start of PathBuffer)
+    fn parse_symlink_reparse_buffer(buffer: &[u8]) -> Option<String> {
+        const PATH_BUFFER_OFFSET: usize = 20;
+        let substitute_name_offset =
+            u16::from_ne_bytes(buffer.get(8..10)?.try_into().ok()?) as usize;
+        let substitute_name_length =
+            u16::from_ne_bytes(buffer.get(10..12)?.try_into().ok()?) as usize;
+
+        let start = PATH_BUFFER_OFFSET + substitute_name_offset;
+        let end = start + substitute_name_length;
+        let (_front, wide, _back) = unsafe { buffer.get(start..end)?.align_to::<u16>() };
+        String::from_utf16(wide).ok()
     }
 
-    fn windows_time_to_git_time(time: LARGE_INTEGER) -> u32 {
-        let mut windows_time = unsafe { *time.QuadPart() };
-        windows_time -= 116444736000000000; /* Windows to Unix Epoch conversion */
-        windows_time /= 10000000;
-        windows_time as u32
+    // FILETIME counts 100-ns ticks since 1601-01-01; `FileStat::mtime` wants nanoseconds since
+    // the Unix epoch, so this shifts the epoch first and then widens the 100-ns ticks out to
+    // nanoseconds, rather than truncating down to whole seconds like the old version did.  That
+    // truncation is exactly the kind of precision loss the "racy git" comparison in
+    // `process_tracked_item`/`DirTreeDiff::is_unmodified` depends on not having: two writes to a
+    // file within the same second would otherwise look identical.
+    fn windows_time_to_git_time(time: LARGE_INTEGER) -> u128 {
+        let mut windows_ticks = unsafe { *time.QuadPart() };
+        windows_ticks -= 116444736000000000; /* Windows to Unix Epoch conversion */
+        windows_ticks as u128 * 100
     }
 }
 
@@ -163,11 +349,17 @@ mod tests {
             .unwrap()
             .duration_since(SystemTime::UNIX_EPOCH)
             .unwrap()
-            .as_secs() as u32;
+            .as_nanos();
         let size = meta.len() as u32;
         assert_eq!(
             dirstat.file_stats.get("one").unwrap(),
-            &FileStat { mtime, size }
+            &FileStat {
+                mtime,
+                size,
+                executable: false,
+                kind: FileKind::Regular,
+                link_target: None,
+            }
         );
     }
 
@@ -188,11 +380,17 @@ mod tests {
                 .unwrap()
                 .duration_since(SystemTime::UNIX_EPOCH)
                 .unwrap()
-                .as_secs() as u32;
+                .as_nanos();
             let size = meta.len() as u32;
             assert_eq!(
                 dirstat.file_stats.get(name).unwrap(),
-                &FileStat { mtime, size }
+                &FileStat {
+                    mtime,
+                    size,
+                    executable: false,
+                    kind: FileKind::Regular,
+                    link_target: None,
+                }
             );
         }
     }
@@ -220,4 +418,21 @@ mod tests {
         let dirstat = DirectoryStat::new(&temp_dir);
         assert_eq!(dirstat.file_stats.len(), 0);
     }
+
+    #[test]
+    fn test_symlink_classified_and_target_captured_in_dir_stat() {
+        let temp_dir = temp_tree(vec![Path::new("target.txt")]);
+        std::os::windows::fs::symlink_file("target.txt", temp_dir.join("link")).unwrap();
+
+        let dirstat = DirectoryStat::new(&temp_dir);
+        assert_eq!(dirstat.file_stats.len(), 2);
+
+        let link_stat = dirstat.file_stats.get("link").unwrap();
+        assert_eq!(link_stat.kind, FileKind::SymLink);
+        assert_eq!(link_stat.link_target.as_deref(), Some("target.txt"));
+
+        // The symlink's own target, not what it points at, is what should be recorded.
+        let target_stat = dirstat.file_stats.get("target.txt").unwrap();
+        assert_eq!(target_stat.kind, FileKind::Regular);
+    }
 }
@@ -0,0 +1,382 @@
+/*
+ *          Copyright Nick G. 2021.
+ * Distributed under the Boost Software License, Version 1.0.
+ *    (See accompanying file LICENSE or copy at
+ *          https://www.boost.org/LICENSE_1_0.txt)
+ */
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use crate::direntry::{DirEntry, FileKind, FileStat, ObjectType};
+use crate::dirstat::{DirHandle, DirectoryStat};
+use crate::status::{Status, StatusEntry};
+use crate::worktree::{blob_sha1, blob_sha1_of_bytes};
+use crate::Index;
+
+/// A worktree-vs-index status scan built on `DirectoryStat`'s own directory enumeration, rather
+/// than `std::fs::read_dir` plus `fs::metadata` (compare `WorkTree`, which is built that way).
+/// Each directory is scanned once via `DirectoryStat::from_handle` and zipped against the
+/// `Index.entries` list for that same directory-relative path: a name present in both is a type
+/// change if it is a symlink on one side only, and otherwise unmodified or modified by comparing
+/// its `FileStat` against the index's recorded one, falling back to a content hash when the stat
+/// alone can't decide (see `is_unmodified`); a name only on disk is added, and a name only in the
+/// index is deleted.  Only the root directory
+/// is opened from its full path; every directory below it is opened relative to its own parent's
+/// still-open `DirHandle` (see `DirHandle::open_child`), so a deep tree resolves each path
+/// component once instead of re-resolving every ancestor at every level.  Subdirectories are
+/// fanned out to rayon so a large tree scans across cores.
+#[derive(Debug, Default, PartialEq)]
+pub struct DirTreeDiff {
+    pub entries: Vec<StatusEntry>,
+}
+
+impl DirTreeDiff {
+    /// # Arguments
+    /// * `path` - The root of the working tree to scan
+    /// * `index` - The index to compare against, keyed by directory the same way
+    ///   `Index.entries` already is
+    pub fn diff_against_index(path: &PathBuf, index: Index) -> DirTreeDiff {
+        let index = Arc::new(index);
+        let entries = Arc::new(Mutex::new(vec![]));
+        let root_handle = DirHandle::open(path);
+        rayon::scope(|scope| {
+            DirTreeDiff::diff_directory(
+                path.clone(),
+                String::new(),
+                root_handle,
+                Arc::clone(&index),
+                Arc::clone(&entries),
+                scope,
+            );
+        });
+
+        let mut entries = Arc::try_unwrap(entries).unwrap().into_inner().unwrap();
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        DirTreeDiff { entries }
+    }
+
+    fn diff_directory(
+        abs_path: PathBuf,
+        relative_dir: String,
+        dir_handle: DirHandle,
+        index: Arc<Index>,
+        entries: Arc<Mutex<Vec<StatusEntry>>>,
+        scope: &rayon::Scope,
+    ) {
+        let (dir_stat, sub_dirs) =
+            DirectoryStat::from_handle(&dir_handle, abs_path.to_str().unwrap().to_string());
+        let index_dir_entries = index.entries.get(&relative_dir);
+
+        let mut local = vec![];
+        for (name, disk_stat) in &dir_stat.file_stats {
+            let index_entry = index_dir_entries.and_then(|dir| dir.iter().find(|e| &e.name == name));
+            let state = match index_entry {
+                Some(index_entry) if DirTreeDiff::type_changed(disk_stat, index_entry) => {
+                    Status::TypeChange
+                }
+                Some(index_entry)
+                    if DirTreeDiff::is_unmodified(
+                        &abs_path,
+                        name,
+                        disk_stat,
+                        index_entry,
+                        index.index_mtime,
+                    ) =>
+                {
+                    continue
+                }
+                Some(_) => Status::Modified(None),
+                None => Status::New,
+            };
+            local.push(StatusEntry {
+                name: DirTreeDiff::join(&relative_dir, name),
+                state,
+            });
+        }
+
+        if let Some(index_dir_entries) = index_dir_entries {
+            for index_entry in index_dir_entries {
+                if !dir_stat.file_stats.contains_key(&index_entry.name) {
+                    local.push(StatusEntry {
+                        name: DirTreeDiff::join(&relative_dir, &index_entry.name),
+                        state: Status::Deleted,
+                    });
+                }
+            }
+        }
+
+        entries.lock().unwrap().extend(local);
+
+        for name in sub_dirs {
+            if name == ".git" {
+                continue;
+            }
+            let sub_abs_path = abs_path.join(&name);
+            let sub_relative_dir = DirTreeDiff::join(&relative_dir, &name);
+            let sub_handle = dir_handle.open_child(&name);
+            let index = Arc::clone(&index);
+            let entries = Arc::clone(&entries);
+            scope.spawn(move |s| {
+                DirTreeDiff::diff_directory(
+                    sub_abs_path,
+                    sub_relative_dir,
+                    sub_handle,
+                    index,
+                    entries,
+                    s,
+                );
+            });
+        }
+    }
+
+    fn join(dir: &str, name: &str) -> String {
+        if dir.is_empty() {
+            name.to_string()
+        } else {
+            format!("{}/{}", dir, name)
+        }
+    }
+
+    // Mirrors `WorkTree`'s own `dir_entry.is_symlink != (index_entry.object_type ==
+    // ObjectType::SymLink)` check: a file becoming a symlink (or vice versa) is a type change,
+    // not a content modification, and has to be caught before `is_unmodified`'s stat/size
+    // comparisons, which don't mean anything across that boundary.
+    fn type_changed(disk_stat: &FileStat, index_entry: &DirEntry) -> bool {
+        (disk_stat.kind == FileKind::SymLink) != (index_entry.object_type == ObjectType::SymLink)
+    }
+
+    // Mirrors `WorkTree`'s own `process_tracked_item`: an exact stat match is still not
+    // necessarily clean when the file's mtime is at or after `index_mtime`, the moment the index
+    // itself was last written, since an edit landing in that same instant ("racy git") could
+    // leave an identical stat behind; and a stat that differs but keeps the same size, or whose
+    // mtime is at or after the index entry's recorded one, isn't necessarily modified either.  Either ambiguous case falls back to a real blob
+    // hash comparison rather than trusting the stat alone.
+    //
+    // `type_changed` having already ruled out a kind mismatch, a `SymLink` entry here is a
+    // symlink on both sides - comparing on-disk size/mtime doesn't mean anything for it, so its
+    // target text is hashed and compared to the index's symlink blob directly instead.
+    fn is_unmodified(
+        dir_path: &Path,
+        name: &str,
+        disk_stat: &FileStat,
+        index_entry: &DirEntry,
+        index_mtime: Option<u128>,
+    ) -> bool {
+        if disk_stat.kind == FileKind::SymLink {
+            return disk_stat
+                .link_target
+                .as_deref()
+                .map(|target| blob_sha1_of_bytes(target.as_bytes()) == index_entry.sha)
+                .unwrap_or(false);
+        }
+
+        if disk_stat == &index_entry.stat {
+            let ambiguous = index_mtime.map_or(false, |index_mtime| disk_stat.mtime >= index_mtime);
+            return !ambiguous || !DirTreeDiff::content_changed(dir_path, name, index_entry);
+        }
+
+        let racy =
+            disk_stat.size == index_entry.stat.size || disk_stat.mtime >= index_entry.stat.mtime;
+        racy && !DirTreeDiff::content_changed(dir_path, name, index_entry)
+    }
+
+    fn content_changed(dir_path: &Path, name: &str, index_entry: &DirEntry) -> bool {
+        match blob_sha1(&dir_path.join(name)) {
+            Ok(sha) => sha != index_entry.sha,
+            Err(_) => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use git2::{Repository, Signature, Time};
+    use std::fs;
+    use temp_testdir::TempDir;
+
+    // Create a test repo to be able to compare the index to the working tree.
+    fn test_repo(path: &std::path::Path, files: &Vec<&std::path::Path>) -> Index {
+        let repo = Repository::init(path).unwrap();
+        let mut index = repo.index().unwrap();
+        let root = repo.path().parent().unwrap();
+        for file in files {
+            let full_path = root.join(file);
+            fs::create_dir_all(full_path.parent().unwrap()).unwrap();
+            fs::write(&full_path, file.to_str().unwrap()).unwrap();
+            index.add_path(file).unwrap();
+        }
+        index.write().unwrap();
+        let tree_oid = index.write_tree().unwrap();
+        let tree = repo.find_tree(tree_oid).unwrap();
+        let signature = Signature::new("Tucan", "me@me.com", &Time::new(20, 0)).unwrap();
+        repo.commit(
+            Option::from("HEAD"),
+            &signature,
+            &signature,
+            "A message",
+            &tree,
+            &[],
+        )
+        .unwrap();
+        Index::new(&path.join(".git/index")).unwrap()
+    }
+
+    // Like `test_repo`, but `link_name` is added to the index as a symlink pointing at `target`
+    // rather than a plain file - `index.add_path` stats the entry itself, so creating a real
+    // symlink here is what gets it recorded as one (mode 120000) instead of a regular file.
+    fn test_repo_with_symlink(path: &std::path::Path, link_name: &str, target: &str) -> Index {
+        let repo = Repository::init(path).unwrap();
+        let mut index = repo.index().unwrap();
+        let root = repo.path().parent().unwrap();
+        std::os::windows::fs::symlink_file(target, root.join(link_name)).unwrap();
+        index.add_path(std::path::Path::new(link_name)).unwrap();
+        index.write().unwrap();
+        let tree_oid = index.write_tree().unwrap();
+        let tree = repo.find_tree(tree_oid).unwrap();
+        let signature = Signature::new("Tucan", "me@me.com", &Time::new(20, 0)).unwrap();
+        repo.commit(
+            Option::from("HEAD"),
+            &signature,
+            &signature,
+            "A message",
+            &tree,
+            &[],
+        )
+        .unwrap();
+        Index::new(&path.join(".git/index")).unwrap()
+    }
+
+    #[test]
+    fn test_nothing_modified() {
+        let temp_dir = TempDir::default();
+        let index = test_repo(&temp_dir, &vec![std::path::Path::new("one.txt")]);
+
+        let value = DirTreeDiff::diff_against_index(&temp_dir.to_path_buf(), index);
+        assert_eq!(value.entries, vec![]);
+    }
+
+    #[test]
+    fn test_a_modified_file() {
+        let temp_dir = TempDir::default();
+        let index = test_repo(&temp_dir, &vec![std::path::Path::new("one.txt")]);
+        fs::write(temp_dir.join("one.txt"), "a much longer modified body").unwrap();
+
+        let value = DirTreeDiff::diff_against_index(&temp_dir.to_path_buf(), index);
+        assert_eq!(
+            value.entries,
+            vec![StatusEntry {
+                name: "one.txt".to_string(),
+                state: Status::Modified(None),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_an_added_file() {
+        let temp_dir = TempDir::default();
+        let index = test_repo(&temp_dir, &vec![std::path::Path::new("one.txt")]);
+        fs::write(temp_dir.join("new.txt"), "new").unwrap();
+
+        let value = DirTreeDiff::diff_against_index(&temp_dir.to_path_buf(), index);
+        assert_eq!(
+            value.entries,
+            vec![StatusEntry {
+                name: "new.txt".to_string(),
+                state: Status::New,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_a_deleted_file() {
+        let temp_dir = TempDir::default();
+        let index = test_repo(&temp_dir, &vec![std::path::Path::new("one.txt")]);
+        fs::remove_file(temp_dir.join("one.txt")).unwrap();
+
+        let value = DirTreeDiff::diff_against_index(&temp_dir.to_path_buf(), index);
+        assert_eq!(
+            value.entries,
+            vec![StatusEntry {
+                name: "one.txt".to_string(),
+                state: Status::Deleted,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_ambiguous_mtime_with_unchanged_content_is_not_modified() {
+        let temp_dir = TempDir::default();
+        let mut index = test_repo(&temp_dir, &vec![std::path::Path::new("one.txt")]);
+
+        // An index write time at the Unix epoch makes every on-disk mtime "at or after" it, so
+        // the exact stat match below can't be trusted on its own and must fall back to a real
+        // content comparison - which should still come back clean, since nothing was touched.
+        index.index_mtime = Some(0);
+
+        let value = DirTreeDiff::diff_against_index(&temp_dir.to_path_buf(), index);
+        assert_eq!(value.entries, vec![]);
+    }
+
+    #[test]
+    fn test_recurses_into_subdirectories() {
+        let temp_dir = TempDir::default();
+        let nested = std::path::Path::new("a_dir/nested.txt");
+        let index = test_repo(&temp_dir, &vec![nested]);
+        fs::write(temp_dir.join(nested), "a much longer modified body").unwrap();
+
+        let value = DirTreeDiff::diff_against_index(&temp_dir.to_path_buf(), index);
+        assert_eq!(
+            value.entries,
+            vec![StatusEntry {
+                name: "a_dir/nested.txt".to_string(),
+                state: Status::Modified(None),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_an_unmodified_symlink_is_not_reported() {
+        let temp_dir = TempDir::default();
+        let index = test_repo_with_symlink(&temp_dir, "link", "target.txt");
+
+        let value = DirTreeDiff::diff_against_index(&temp_dir.to_path_buf(), index);
+        assert_eq!(value.entries, vec![]);
+    }
+
+    #[test]
+    fn test_a_retargeted_symlink_is_modified() {
+        let temp_dir = TempDir::default();
+        let index = test_repo_with_symlink(&temp_dir, "link", "target.txt");
+        fs::remove_file(temp_dir.join("link")).unwrap();
+        std::os::windows::fs::symlink_file("somewhere/else", temp_dir.join("link")).unwrap();
+
+        let value = DirTreeDiff::diff_against_index(&temp_dir.to_path_buf(), index);
+        assert_eq!(
+            value.entries,
+            vec![StatusEntry {
+                name: "link".to_string(),
+                state: Status::Modified(None),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_a_symlink_replacing_a_tracked_file_is_a_type_change() {
+        let temp_dir = TempDir::default();
+        let index = test_repo(&temp_dir, &vec![std::path::Path::new("simple_file.txt")]);
+        fs::remove_file(temp_dir.join("simple_file.txt")).unwrap();
+        std::os::windows::fs::symlink_file("somewhere/else", temp_dir.join("simple_file.txt"))
+            .unwrap();
+
+        let value = DirTreeDiff::diff_against_index(&temp_dir.to_path_buf(), index);
+        assert_eq!(
+            value.entries,
+            vec![StatusEntry {
+                name: "simple_file.txt".to_string(),
+                state: Status::TypeChange,
+            }]
+        );
+    }
+}
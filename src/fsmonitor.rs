@@ -0,0 +1,353 @@
+/*
+ *          Copyright Nick G. 2021.
+ * Distributed under the Boost Software License, Version 1.0.
+ *    (See accompanying file LICENSE or copy at
+ *          https://www.boost.org/LICENSE_1_0.txt)
+ */
+
+// An optional filesystem-monitor fast path, modeled on jj's `FsmonitorKind`.
+//
+// When a watcher is configured, a status run can hand `query_watchman` the clock token it was
+// given last time and get back only the paths that changed since then, instead of walking the
+// whole worktree with `fs::read_dir`.  A missing or stale clock always falls back to a full
+// rescan, so correctness never depends on the watcher being installed, running, or caught up.
+
+use crate::direntry::{FileKind, FileStat};
+use crate::error::StatusError;
+use std::collections::BTreeMap;
+#[cfg(unix)]
+use std::io::{Read, Write};
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+use std::path::Path;
+use std::time::Duration;
+
+/// Which filesystem-monitor integration (if any) should be queried instead of doing a full
+/// worktree walk.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FsmonitorKind {
+    None,
+    Watchman,
+}
+
+/// A single path Watchman reported as changed since the last query.
+#[derive(Debug, PartialEq, Clone)]
+pub struct FsmonitorEntry {
+    pub name: String,
+    pub exists: bool,
+    pub stat: FileStat,
+}
+
+/// The last-seen worktree state a status run hands back to `query_watchman` next time, so only
+/// the paths that changed since `clock` need to be restated.
+#[derive(Debug, Default, Clone)]
+pub struct FsmonitorState {
+    pub clock: Option<String>,
+    pub files: BTreeMap<String, FileStat>,
+}
+
+impl FsmonitorState {
+    /// Folds `changed` into this state, returning the paths that still exist (to be restated
+    /// against the index) and the paths that no longer do (to be routed through
+    /// `process_deleted_item`).
+    pub fn apply(&mut self, changed: Vec<FsmonitorEntry>) -> (Vec<String>, Vec<String>) {
+        let mut modified = vec![];
+        let mut deleted = vec![];
+        for entry in changed {
+            if entry.exists {
+                self.files.insert(entry.name.clone(), entry.stat.clone());
+                modified.push(entry.name);
+            } else {
+                self.files.remove(&entry.name);
+                deleted.push(entry.name);
+            }
+        }
+        (modified, deleted)
+    }
+}
+
+/// Queries Watchman for the paths under `root` that changed since `state.clock`, updates `state`
+/// in place, and returns the paths that need to be restated against the index and the paths
+/// that no longer exist.  Returns an error (rather than an empty result) whenever the caller
+/// should fall back to a full rescan: no prior clock, or Watchman rejecting it as too old.
+///
+/// Watchman listens on a Unix domain socket on Linux/macOS and a named pipe on Windows; only the
+/// former is wired up so far (see the `#[cfg(not(unix))]` stub below), the latter is a known
+/// follow-up for this Windows-focused crate.
+#[cfg(unix)]
+pub fn query_watchman(
+    root: &Path,
+    state: &mut FsmonitorState,
+) -> Result<(Vec<String>, Vec<String>), StatusError> {
+    let clock = state.clock.as_deref().ok_or_else(|| StatusError {
+        message: "No fsmonitor clock to query from; a full rescan is required".to_string(),
+    })?;
+
+    let socket_path = watchman_sockname()?;
+    let mut stream = UnixStream::connect(socket_path)?;
+
+    let root = root.to_str().unwrap();
+    let query = format!(
+        r#"["query", "{}", {{"since": "{}", "fields": ["name", "exists", "mtime_ms", "size"]}}]"#,
+        root, clock
+    );
+    stream.write_all(query.as_bytes())?;
+    stream.write_all(b"\n")?;
+
+    let response = read_watchman_response(&mut stream)?;
+
+    let (new_clock, changed) = parse_watchman_response(&response)?;
+    state.clock = Some(new_clock);
+    Ok(state.apply(changed))
+}
+
+/// Watchman's named-pipe transport isn't implemented yet, so on non-Unix platforms this always
+/// reports that a full rescan is required rather than silently pretending nothing changed.
+#[cfg(not(unix))]
+pub fn query_watchman(
+    _root: &Path,
+    _state: &mut FsmonitorState,
+) -> Result<(Vec<String>, Vec<String>), StatusError> {
+    Err(StatusError {
+        message: "Watchman fsmonitor support is only wired up over a Unix domain socket; a full rescan is required".to_string(),
+    })
+}
+
+// Watchman keeps its end of the socket open for reuse rather than closing it after a single
+// reply, so `read_to_string`'s usual "read until EOF" would block forever waiting for a close
+// that never comes. Instead this reads incrementally and stops as soon as a complete top-level
+// JSON object has arrived, tracking brace depth the same (string-unaware, good-enough-for-a-
+// reply-we-control-both-ends-of) way `parse_watchman_files` already does.
+#[cfg(unix)]
+fn read_watchman_response(stream: &mut UnixStream) -> Result<String, StatusError> {
+    let mut response = Vec::new();
+    let mut depth = 0i32;
+    let mut started = false;
+    let mut chunk = [0u8; 4096];
+    loop {
+        let read = stream.read(&mut chunk)?;
+        if read == 0 {
+            break;
+        }
+        for &byte in &chunk[..read] {
+            response.push(byte);
+            match byte {
+                b'{' => {
+                    depth += 1;
+                    started = true;
+                }
+                b'}' => depth -= 1,
+                _ => {}
+            }
+        }
+        if started && depth <= 0 {
+            break;
+        }
+    }
+    Ok(String::from_utf8_lossy(&response).into_owned())
+}
+
+// Watchman's socket location is discovered via `watchman get-sockname` rather than
+// reimplementing its own platform-specific socket-discovery logic here.
+#[cfg(unix)]
+fn watchman_sockname() -> Result<String, StatusError> {
+    let output = std::process::Command::new("watchman")
+        .arg("get-sockname")
+        .output()?;
+    extract_string_field(&String::from_utf8_lossy(&output.stdout), "sockname").ok_or_else(|| {
+        StatusError {
+            message: "Could not determine the Watchman socket path".to_string(),
+        }
+    })
+}
+
+// Watchman's reply is a single JSON object containing a new "clock" and a "files" array of
+// objects with our requested fields.  Pulling out just those fields with simple string
+// scanning keeps this dependency-free rather than pulling in a full JSON parser for one query
+// shape we control both ends of.
+fn parse_watchman_response(response: &str) -> Result<(String, Vec<FsmonitorEntry>), StatusError> {
+    let clock = extract_string_field(response, "clock").ok_or_else(|| StatusError {
+        message: "Watchman response did not include a clock".to_string(),
+    })?;
+
+    let files_start = response.find("\"files\"").and_then(|i| response[i..].find('[').map(|j| i + j + 1));
+    let files = match files_start {
+        Some(start) => {
+            let end = response[start..]
+                .find(']')
+                .ok_or_else(|| StatusError {
+                    message: "Watchman response had an unterminated \"files\" array".to_string(),
+                })?
+                + start;
+            parse_watchman_files(&response[start..end])
+        }
+        // No "files" key at all means nothing changed since `clock`.
+        None => vec![],
+    };
+
+    Ok((clock, files))
+}
+
+fn parse_watchman_files(body: &str) -> Vec<FsmonitorEntry> {
+    let mut entries = vec![];
+    let mut depth = 0i32;
+    let mut object_start = None;
+    for (i, c) in body.char_indices() {
+        match c {
+            '{' => {
+                if depth == 0 {
+                    object_start = Some(i);
+                }
+                depth += 1;
+            }
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    if let Some(start) = object_start.take() {
+                        if let Some(entry) = parse_watchman_file_object(&body[start..=i]) {
+                            entries.push(entry);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+    entries
+}
+
+fn parse_watchman_file_object(object: &str) -> Option<FsmonitorEntry> {
+    let name = extract_string_field(object, "name")?;
+    let exists = extract_bool_field(object, "exists").unwrap_or(true);
+    let mtime_ms = extract_number_field(object, "mtime_ms").unwrap_or(0);
+    let size = extract_number_field(object, "size").unwrap_or(0);
+    Some(FsmonitorEntry {
+        name,
+        exists,
+        stat: FileStat {
+            mtime: Duration::from_millis(mtime_ms).as_nanos(),
+            size: size as u32,
+            // Mode isn't among the fields queried from Watchman yet, so a permission-only
+            // change won't be detected through this fast path.
+            executable: false,
+            kind: FileKind::Regular,
+            link_target: None,
+        },
+    })
+}
+
+fn extract_bool_field(object: &str, field: &str) -> Option<bool> {
+    let needle = format!("\"{}\"", field);
+    let after_key = &object[object.find(&needle)? + needle.len()..];
+    let after_colon = after_key[after_key.find(':')? + 1..].trim_start();
+    Some(after_colon.starts_with("true"))
+}
+
+fn extract_string_field(object: &str, field: &str) -> Option<String> {
+    let needle = format!("\"{}\"", field);
+    let after_key = &object[object.find(&needle)? + needle.len()..];
+    let value_start = after_key.find('"')? + 1;
+    let value_end = after_key[value_start..].find('"')? + value_start;
+    Some(after_key[value_start..value_end].to_string())
+}
+
+fn extract_number_field(object: &str, field: &str) -> Option<u64> {
+    let needle = format!("\"{}\"", field);
+    let after_key = &object[object.find(&needle)? + needle.len()..];
+    let after_colon = &after_key[after_key.find(':')? + 1..];
+    let digits: String = after_colon
+        .trim_start()
+        .chars()
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    digits.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_state_apply_tracks_modified_and_deleted_paths() {
+        let mut state = FsmonitorState::default();
+        state
+            .files
+            .insert("stays.txt".to_string(), FileStat::default());
+
+        let (modified, deleted) = state.apply(vec![
+            FsmonitorEntry {
+                name: "new.txt".to_string(),
+                exists: true,
+                stat: FileStat {
+                    mtime: 1,
+                    size: 2,
+                    executable: false,
+                    kind: FileKind::Regular,
+                    link_target: None,
+                },
+            },
+            FsmonitorEntry {
+                name: "gone.txt".to_string(),
+                exists: false,
+                stat: FileStat::default(),
+            },
+        ]);
+
+        assert_eq!(modified, vec!["new.txt".to_string()]);
+        assert_eq!(deleted, vec!["gone.txt".to_string()]);
+        assert!(state.files.contains_key("new.txt"));
+        assert!(state.files.contains_key("stays.txt"));
+        assert!(!state.files.contains_key("gone.txt"));
+    }
+
+    #[test]
+    fn test_parse_watchman_response_extracts_clock_and_files() {
+        let response = r#"{"version": "2021.01.01.00", "clock": "c:1234:56", "files": [
+            {"name": "src/index.rs", "exists": true, "mtime_ms": 1000, "size": 42},
+            {"name": "src/old.rs", "exists": false, "mtime_ms": 0, "size": 0}
+        ]}"#;
+
+        let (clock, files) = parse_watchman_response(response).unwrap();
+        assert_eq!(clock, "c:1234:56");
+        assert_eq!(files.len(), 2);
+        assert_eq!(files[0].name, "src/index.rs");
+        assert!(files[0].exists);
+        assert_eq!(files[0].stat.size, 42);
+        assert!(!files[1].exists);
+    }
+
+    #[test]
+    fn test_parse_watchman_response_with_no_files_key_means_nothing_changed() {
+        let response = r#"{"version": "2021.01.01.00", "clock": "c:1234:99"}"#;
+        let (clock, files) = parse_watchman_response(response).unwrap();
+        assert_eq!(clock, "c:1234:99");
+        assert!(files.is_empty());
+    }
+
+    #[test]
+    fn test_query_watchman_without_a_clock_requires_a_full_rescan() {
+        let mut state = FsmonitorState::default();
+        let result = query_watchman(Path::new("."), &mut state);
+        assert!(result.is_err());
+    }
+
+    // Regression test for the hang this crate used to have: Watchman keeps its end of the
+    // socket open for reuse instead of closing it after a single reply, so a `read_to_string`
+    // waiting for EOF would block forever even though the full JSON reply already arrived.
+    #[cfg(unix)]
+    #[test]
+    fn test_read_watchman_response_stops_at_the_closing_brace_without_waiting_for_the_peer_to_close() {
+        let (mut reader, mut writer) = UnixStream::pair().unwrap();
+        let json = r#"{"version": "2021.01.01.00", "clock": "c:1234:56"}"#;
+        let handle = std::thread::spawn(move || {
+            writer.write_all(json.as_bytes()).unwrap();
+            // Held open (not dropped) for a while to stand in for Watchman's real long-lived
+            // connection; a broken reader would hang here rather than returning already.
+            std::thread::sleep(Duration::from_millis(200));
+        });
+
+        let response = read_watchman_response(&mut reader).unwrap();
+        assert_eq!(response, json);
+        handle.join().unwrap();
+    }
+}
@@ -14,15 +14,17 @@ use nom::take;
 use nom::take_bits;
 use nom::tuple;
 
+use memmap2::Mmap;
 use nom::do_parse;
 use nom::IResult;
+use sha1::{Digest, Sha1};
 use std::convert::TryInto;
 use std::fs::File;
-use std::io::Read;
 use std::path::Path;
-use std::time::Duration;
+use std::time::{Duration, UNIX_EPOCH};
 
-use crate::direntry::{DirEntry, FileStat, ObjectType};
+use crate::direntry::{DirEntry, FileKind, FileStat, ObjectType};
+use crate::pathspec::Pathspec;
 
 use crate::error::StatusError;
 use std::collections::HashMap;
@@ -35,7 +37,7 @@ impl From<nom::Err<nom::error::Error<&[u8]>>> for StatusError {
     }
 }
 
-// A function for parsing the name size of an index entry.
+// A function for parsing the name size and merge stage of an index entry.
 // This assumes the input is at the 16 bit flags field.
 //
 //      A 16-bit 'flags' field split into (high to low bits)
@@ -45,8 +47,8 @@ impl From<nom::Err<nom::error::Error<&[u8]>>> for StatusError {
 //      - 12-bit name length if the length is less than 0xFFF; otherwise 0xFFF is stored in this
 //        field.
 //
-// Note: This currently throws away the `stage` entry which means this doesn't properly handle
-//       merged files.
+// The stage is 0 for a normal, unconflicted entry.  During a merge conflict git instead keeps up
+// to three entries for the same path: 1 (base), 2 (ours), 3 (theirs).
 //
 // To be honest, I'm not sure exactly why I wasn't able to do this in place next to the rest of
 // the entry parsing, I think it has to do with treating the byte stream as bits.
@@ -56,15 +58,69 @@ impl From<nom::Err<nom::error::Error<&[u8]>>> for StatusError {
 //
 // Also trying to put this as a function in the impl block for Index resulted in some compilation
 // errors.  Not sure on why, my macro knowledge is next to nothing.
-fn parse_name_size(input: &[u8]) -> IResult<&[u8], u16> {
-    let (input, b): (&[u8], (u8, u8, u16)) = do_parse!(
+//
+// Returns `(name_size, stage, assume_valid, extended)`.
+fn parse_name_size(input: &[u8]) -> IResult<&[u8], (u16, u8, bool, bool)> {
+    let (input, b): (&[u8], (u8, u8, u8, u16)) = do_parse!(
+        input,
+        b: bits!(tuple!(
+            take_bits!(1u8),
+            take_bits!(1u8),
+            take_bits!(2u8),
+            take_bits!(12u16)
+        )) >> (b)
+    )?;
+    // I tried to just return the tuple from the do_parse macro, but I kept hitting compiler errors
+    // so I decided to fall back to full parse there and access the tuple entries here outside of
+    // the do_parse
+    Ok((input, (b.3, b.2, b.0 != 0, b.1 != 0)))
+}
+
+// A function for parsing the version 3+ extended flags word, only present when the base flags'
+// extended bit is set.  This carries two more booleans, the rest of the bits are reserved for
+// future use.
+//
+//      A 16-bit 'extended flags' field split into (high to low bits)
+//      - 1-bit reserved (must be zero)
+//      - 1-bit skip-worktree flag
+//      - 1-bit intent-to-add flag
+//      - 13-bit unused
+fn parse_extended_flags(input: &[u8]) -> IResult<&[u8], (bool, bool)> {
+    let (input, b): (&[u8], (u8, u8, u8, u16)) = do_parse!(
         input,
-        b: bits!(tuple!(take_bits!(2u8), take_bits!(2u8), take_bits!(12u16))) >> (b)
+        b: bits!(tuple!(
+            take_bits!(1u8),
+            take_bits!(1u8),
+            take_bits!(1u8),
+            take_bits!(13u16)
+        )) >> (b)
     )?;
-    // I tried to just return the u16 from the do_parse macro, but I kept hitting compiler errors
-    // so I decided to fall back to full parse there and access the tuple entry here outside of the
-    // do_parse
-    Ok((input, b.2))
+    Ok((input, (b.1 != 0, b.2 != 0)))
+}
+
+// Decodes a git offset-varint: the number of trailing bytes of the previous path to strip before
+// appending a version 4 entry's NUL-terminated path suffix.
+//
+// Read a byte, `value = byte & 0x7f`; while the high bit is set, read the next byte and set
+// `value = ((value + 1) << 7) | (next & 0x7f)`.
+fn decode_varint(input: &[u8]) -> IResult<&[u8], usize> {
+    let mut offset = 0;
+    let mut byte = input[offset];
+    offset += 1;
+    let mut value: usize = (byte & 0x7f) as usize;
+    while byte & 0x80 != 0 {
+        byte = input[offset];
+        offset += 1;
+        value = ((value + 1) << 7) | (byte & 0x7f) as usize;
+    }
+    Ok((&input[offset..], value))
+}
+
+// Reads the bytes up to (and consuming) the next NUL byte, as used for a version 4 entry's path
+// suffix.
+fn take_until_nul(input: &[u8]) -> IResult<&[u8], &[u8]> {
+    let end = input.iter().position(|&b| b == 0).unwrap();
+    Ok((&input[end + 1..], &input[..end]))
 }
 
 /// An index of a repo.
@@ -83,6 +139,23 @@ pub struct Index {
     oid: [u8; 20],
     header: Header,
     pub entries: HashMap<String, Vec<DirEntry>>,
+
+    // The "TREE" extension caches the oid of the tree each directory in the index would produce,
+    // keyed by the directory's relative path ("" for the root).  A directory is absent from this
+    // map when git has invalidated its cached oid (e.g. the directory is known to be dirty).
+    cache_tree: HashMap<String, [u8; 20]>,
+
+    // The trailing checksum git wrote over every preceding byte of the index file.  Verified
+    // against the file's contents in `Index::new`, then kept around for callers who want to
+    // compare it without re-reading the file.
+    checksum: [u8; 20],
+
+    // When this index file was last written, in nanoseconds since the unix epoch (same units as
+    // `FileStat::mtime`).  This is the "racy git" reference clock: a work tree entry whose mtime
+    // is at or after this instant was touched around the same time the index was saved, so its
+    // stat can't be trusted to tell clean from modified and must be re-hashed.  `None` when the
+    // index file's own mtime couldn't be read, e.g. a synthetic `Index` built in a test.
+    pub index_mtime: Option<u128>,
 }
 
 #[derive(PartialEq, Eq, Debug, Default, Clone)]
@@ -99,26 +172,75 @@ impl Index {
     /// * `path` - The path to a git repo.  This logic will _not_ search up parent directories for
     ///     a git repo
     pub fn new(path: &Path) -> Result<Index, StatusError> {
-        let oid: [u8; 20] = [0; 20];
-        let mut buffer: Vec<u8> = Vec::new();
-        File::open(&path).and_then(|mut f| f.read_to_end(&mut buffer))?;
+        // Index files can run to tens of thousands of entries; memory-mapping avoids the
+        // read-into-`Vec` copy of the whole file before parsing even begins.  `Mmap::map` is
+        // unsafe because the file could be truncated out from under us by another process while
+        // it's mapped, which would turn our slice reads into a SIGBUS; we accept that risk here
+        // the same way git itself does when it mmaps the index.
+        let file = File::open(&path)?;
+        let index_mtime = file
+            .metadata()
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .map(|mtime| mtime.duration_since(UNIX_EPOCH).unwrap().as_nanos());
+        let buffer = unsafe { Mmap::map(&file)? };
+        let checksum = Index::verify_checksum(&buffer)?;
         let (mut contents, header) = Index::read_header(&buffer)?;
         let mut entries = HashMap::new();
+        let mut previous_path = String::new();
         for _ in 0..header.entries {
-            let (local_contents, (directory, entry)) = Index::read_entry(contents)?;
+            let (local_contents, (directory, entry)) =
+                Index::read_entry(contents, header.version, &previous_path)?;
+            previous_path = if directory.is_empty() {
+                entry.name.clone()
+            } else {
+                format!("{}/{}", directory, entry.name)
+            };
             let directory_entry = Index::get_directory_entry(&directory, &mut entries);
             directory_entry.push(entry);
             contents = local_contents;
         }
+
+        let (_, cache_tree) = Index::read_extensions(contents)?;
+        let oid = cache_tree.get("").copied().unwrap_or([0; 20]);
+
         let index = Index {
             path: String::from(path.to_str().unwrap()),
             oid,
             header,
             entries,
+            cache_tree,
+            checksum,
+            index_mtime,
         };
         Ok(index)
     }
 
+    // Validates the trailing checksum git writes over every preceding byte of the index file and
+    // returns it.  This only supports the SHA-1 checksums used by the default `sha1` index
+    // format; sha256 repos write a wider trailer and aren't handled here.
+    fn verify_checksum(buffer: &[u8]) -> Result<[u8; 20], StatusError> {
+        if buffer.len() < 20 {
+            return Err(StatusError {
+                message: "Index file is too small to contain a checksum".to_string(),
+            });
+        }
+        let (content, trailer) = buffer.split_at(buffer.len() - 20);
+        let actual: [u8; 20] = Sha1::digest(content).into();
+        if actual.as_slice() != trailer {
+            return Err(StatusError {
+                message: "Index checksum does not match its contents, the index may be corrupt"
+                    .to_string(),
+            });
+        }
+        Ok(actual)
+    }
+
+    /// Returns the trailing checksum git wrote over the index file's contents.
+    pub fn checksum(&self) -> &[u8] {
+        &self.checksum
+    }
+
     /// Returns the oid(Object ID) for the index.
     ///
     /// The object ID of an index is the object ID of the tree which the index represents.
@@ -126,6 +248,131 @@ impl Index {
         &self.oid
     }
 
+    /// Returns the index's cached tree oid for `directory` (`""` for the root), if git hasn't
+    /// invalidated it. A caller that already knows the corresponding oid on another side of a
+    /// comparison (e.g. the same path in `HEAD`'s tree) can compare the two directly and skip
+    /// walking the directory's contents entirely when they match, the same way git itself avoids
+    /// re-hashing a tree it already knows is unchanged.
+    pub fn cached_tree_oid(&self, directory: &str) -> Option<[u8; 20]> {
+        self.cache_tree.get(directory).copied()
+    }
+
+    /// Returns the conflicted (unmerged) entries, grouped by their full relative path.
+    ///
+    /// During a merge conflict git drops the usual single stage-0 entry for a path and instead
+    /// keeps one entry per side that disagreed (stage 1 = base, 2 = ours, 3 = theirs).  This
+    /// groups those entries back together so a caller can tell a path is unmerged and look at
+    /// each side.
+    pub fn unmerged_entries(&self) -> HashMap<String, Vec<&DirEntry>> {
+        let mut unmerged: HashMap<String, Vec<&DirEntry>> = HashMap::new();
+        for (directory, dir_entries) in &self.entries {
+            for entry in dir_entries {
+                if entry.stage == 0 {
+                    continue;
+                }
+                let full_path = if directory.is_empty() {
+                    entry.name.clone()
+                } else {
+                    format!("{}/{}", directory, entry.name)
+                };
+                unmerged.entry(full_path).or_default().push(entry);
+            }
+        }
+        unmerged
+    }
+
+    /// Returns the entries selected by `pathspec`, grouped by their full relative path.
+    ///
+    /// This is the foundation for restricting a status run to a subtree or glob, e.g.
+    /// `win-git-status src/` or `win-git-status '*.rs'`.
+    pub fn entries_matching(&self, pathspec: &Pathspec) -> HashMap<String, &DirEntry> {
+        let mut matching = HashMap::new();
+        for (directory, dir_entries) in &self.entries {
+            for entry in dir_entries {
+                let full_path = if directory.is_empty() {
+                    entry.name.clone()
+                } else {
+                    format!("{}/{}", directory, entry.name)
+                };
+                if pathspec.matches(&full_path, false) {
+                    matching.insert(full_path, entry);
+                }
+            }
+        }
+        matching
+    }
+
+    /// Reads the optional extensions trailing the fixed set of entries, returning the cached
+    /// per-directory tree oids from the `"TREE"` extension, if present.
+    ///
+    /// Every extension is framed as a 4-byte signature, a 4-byte big-endian length, then that
+    /// many bytes of extension-specific body.  Extensions this crate doesn't understand are
+    /// simply skipped over.
+    fn read_extensions(mut stream: &[u8]) -> IResult<&[u8], HashMap<String, [u8; 20]>> {
+        let mut cache_tree = HashMap::new();
+        // The final 20 bytes of the buffer are the trailing index checksum, not an extension.
+        while stream.len() > 20 {
+            let (rest, (signature, length)) = tuple((nom::bytes::complete::take(4usize), be_u32))(stream)?;
+            let (body, rest) = rest.split_at(length as usize);
+            if signature == b"TREE" {
+                cache_tree = Index::read_cache_tree(body);
+            }
+            stream = rest;
+        }
+        Ok((stream, cache_tree))
+    }
+
+    /// Parses the body of a `"TREE"` extension into a map of full directory path to tree oid.
+    ///
+    /// The body is a flat, pre-order sequence of records: a NUL-terminated path, an ASCII
+    /// `entry_count SP subtree_count LF` line, then (unless `entry_count` is `-1`, meaning the
+    /// subtree is invalidated) a 20-byte raw oid.  Every record but the root's stores a path
+    /// relative to its own immediate parent (e.g. `"nested"`, not `"one/nested"`), and
+    /// `subtree_count` says how many of the records immediately following it are its own
+    /// children, recursively - so a stack of (full path, children remaining) for the directories
+    /// currently being descended into is kept here to reassemble each record's full path and to
+    /// know when a directory's subtree has been fully consumed and its ancestor is current again.
+    fn read_cache_tree(mut body: &[u8]) -> HashMap<String, [u8; 20]> {
+        let mut cache_tree = HashMap::new();
+        let mut ancestors: Vec<(String, u32)> = vec![];
+        while !body.is_empty() {
+            let path_end = body.iter().position(|&b| b == 0).unwrap();
+            let name = String::from_utf8(body[..path_end].to_vec()).unwrap();
+            body = &body[path_end + 1..];
+
+            let line_end = body.iter().position(|&b| b == b'\n').unwrap();
+            let line = std::str::from_utf8(&body[..line_end]).unwrap();
+            body = &body[line_end + 1..];
+
+            let mut fields = line.split(' ');
+            let entry_count: i32 = fields.next().unwrap().parse().unwrap();
+            let subtree_count: u32 = fields.next().unwrap().parse().unwrap();
+
+            let path = match ancestors.last() {
+                None => name,
+                Some((parent, _)) if parent.is_empty() => name,
+                Some((parent, _)) => format!("{}/{}", parent, name),
+            };
+
+            if entry_count != -1 {
+                let oid: [u8; 20] = body[..20].try_into().unwrap();
+                body = &body[20..];
+                cache_tree.insert(path.clone(), oid);
+            }
+
+            if let Some((_, remaining)) = ancestors.last_mut() {
+                *remaining -= 1;
+            }
+            if subtree_count > 0 {
+                ancestors.push((path, subtree_count));
+            }
+            while matches!(ancestors.last(), Some((_, 0))) {
+                ancestors.pop();
+            }
+        }
+        cache_tree
+    }
+
     /// Reads in the header from the provided stream
     ///
     ///
@@ -139,9 +386,16 @@ impl Index {
 
     /// Reads in entry from the provided stream
     ///
-    ///
-    fn read_entry(stream: &[u8]) -> IResult<&[u8], (String, DirEntry)> {
-        let (output, (mtime_s, mtime_ns, mode, size, sha, full_name)) = do_parse!(
+    /// `version` selects the on-disk entry layout: versions 2 and 3 store the full path padded
+    /// to an 8-byte boundary, while version 4 prefix-compresses the path against
+    /// `previous_path` (the path of the entry that was read immediately before this one) and
+    /// needs no padding.
+    fn read_entry<'a>(
+        stream: &'a [u8],
+        version: u32,
+        previous_path: &str,
+    ) -> IResult<&'a [u8], (String, DirEntry)> {
+        let (stream, (mtime_s, mtime_ns, mode, size, sha, name_size_and_stage)) = do_parse!(
             stream,
             take!(8)
                 >> mtime_s: be_u32
@@ -151,18 +405,34 @@ impl Index {
                 >> take!(8)
                 >> size: be_u32
                 >> sha: take!(20)
-                >> name_size: parse_name_size
-                >> name: take!(name_size)
-                >> take!(8 - ((62 + name_size) % 8))
-                >> (
-                    mtime_s,
-                    mtime_ns,
-                    mode,
-                    size,
-                    sha,
-                    String::from_utf8(name.to_vec()).unwrap()
-                )
+                >> name_size_and_stage: parse_name_size
+                >> (mtime_s, mtime_ns, mode, size, sha, name_size_and_stage)
         )?;
+        let (name_size, stage, assume_valid, extended) = name_size_and_stage;
+
+        // The fixed-size portion of an entry read so far, used below to compute the padding for
+        // version 2/3 entries.  It grows by 2 when the optional extended flags word is present.
+        let mut fixed_prefix_len = 62;
+        let (stream, skip_worktree, intent_to_add) = if extended && version >= 3 {
+            let (stream, (skip_worktree, intent_to_add)) = parse_extended_flags(stream)?;
+            fixed_prefix_len += 2;
+            (stream, skip_worktree, intent_to_add)
+        } else {
+            (stream, false, false)
+        };
+
+        let (output, full_name) = if version == 4 {
+            let (stream, truncate_length) = decode_varint(stream)?;
+            let (stream, suffix) = take_until_nul(stream)?;
+            let kept = previous_path.len() - truncate_length;
+            let mut full_name = previous_path[..kept].to_string();
+            full_name.push_str(std::str::from_utf8(suffix).unwrap());
+            (stream, full_name)
+        } else {
+            let (stream, name) = take!(stream, name_size)?;
+            let (stream, _) = take!(stream, 8 - ((fixed_prefix_len + name_size) % 8))?;
+            (stream, String::from_utf8(name.to_vec()).unwrap())
+        };
 
         let object_bits = mode >> 12;
         let object_type = match object_bits {
@@ -170,6 +440,9 @@ impl Index {
             0b1010 => ObjectType::SymLink,
             _ => ObjectType::Regular,
         };
+        // Git only ever stores 100644 or 100755 for a regular file; the owner-executable bit is
+        // enough to tell them apart.
+        let executable = mode & 0o100 != 0;
 
         let full_path = Path::new(&full_name);
         let parent_path = full_path.parent().unwrap().to_str().unwrap();
@@ -177,10 +450,20 @@ impl Index {
         // Git times are really a duration since unix Epoch
         let mtime = Duration::new(mtime_s.into(), mtime_ns).as_nanos();
         let entry = DirEntry {
-            stat: FileStat { mtime, size },
+            stat: FileStat {
+                mtime,
+                size,
+                executable,
+                kind: FileKind::Regular,
+                link_target: None,
+            },
             sha: sha.try_into().unwrap(),
             name,
             object_type,
+            stage,
+            assume_valid,
+            skip_worktree,
+            intent_to_add,
         };
         Ok((output, (parent_path.to_string(), entry)))
     }
@@ -286,7 +569,7 @@ mod tests {
         let pad_length = 8 - ((62 + name_length) % 8);
         stream.extend(vec![0; pad_length as usize]);
         assert_eq!(
-            Index::read_entry(&stream),
+            Index::read_entry(&stream, 2, ""),
             Ok((
                 &b""[..],
                 (
@@ -295,10 +578,17 @@ mod tests {
                         stat: FileStat {
                             mtime: (20 * 1_000_000_000) + 25,
                             size: 70,
+                            executable: false,
+                            kind: FileKind::Regular,
+                            link_target: None,
                         },
                         sha: *sha,
                         object_type: ObjectType::Regular,
                         name: "name".to_string(),
+                        stage: 0,
+                        assume_valid: false,
+                        skip_worktree: false,
+                        intent_to_add: false,
                     }
                 )
             ))
@@ -317,16 +607,26 @@ mod tests {
         let pad_length = 8 - ((62 + name_length) % 8);
         stream.extend(vec![0; pad_length as usize]);
         assert_eq!(
-            Index::read_entry(&stream),
+            Index::read_entry(&stream, 2, ""),
             Ok((
                 &b""[..],
                 (
                     "a/different/name/to/a/file".to_string(),
                     DirEntry {
                         object_type: ObjectType::Regular,
-                        stat: FileStat { mtime: 0, size: 0 },
+                        stat: FileStat {
+                            mtime: 0,
+                            size: 0,
+                            executable: false,
+                            kind: FileKind::Regular,
+                            link_target: None,
+                        },
                         sha: *sha,
-                        name: "with.ext".to_string()
+                        name: "with.ext".to_string(),
+                        stage: 0,
+                        assume_valid: false,
+                        skip_worktree: false,
+                        intent_to_add: false,
                     }
                 )
             ))
@@ -346,7 +646,7 @@ mod tests {
         stream.extend(vec![0; pad_length as usize]);
         let suffix = b"what";
         stream.extend(suffix);
-        let read = Index::read_entry(&stream);
+        let read = Index::read_entry(&stream, 2, "");
         assert_eq!(
             read,
             Ok((
@@ -355,9 +655,19 @@ mod tests {
                     "a".to_string(),
                     DirEntry {
                         object_type: ObjectType::Regular,
-                        stat: FileStat { mtime: 0, size: 0 },
+                        stat: FileStat {
+                            mtime: 0,
+                            size: 0,
+                            executable: false,
+                            kind: FileKind::Regular,
+                            link_target: None,
+                        },
                         sha: *sha,
-                        name: "file".to_string()
+                        name: "file".to_string(),
+                        stage: 0,
+                        assume_valid: false,
+                        skip_worktree: false,
+                        intent_to_add: false,
                     }
                 )
             ))
@@ -377,7 +687,7 @@ mod tests {
         stream.extend(vec![0; pad_length as usize]);
         let suffix = b"sure";
         stream.extend(suffix);
-        let read = Index::read_entry(&stream);
+        let read = Index::read_entry(&stream, 2, "");
         assert_eq!(
             read,
             Ok((
@@ -386,9 +696,19 @@ mod tests {
                     "".to_string(),
                     DirEntry {
                         object_type: ObjectType::Regular,
-                        stat: FileStat { mtime: 0, size: 0 },
+                        stat: FileStat {
+                            mtime: 0,
+                            size: 0,
+                            executable: false,
+                            kind: FileKind::Regular,
+                            link_target: None,
+                        },
                         sha: *sha,
-                        name: "niners999".to_string()
+                        name: "niners999".to_string(),
+                        stage: 0,
+                        assume_valid: false,
+                        skip_worktree: false,
+                        intent_to_add: false,
                     }
                 )
             ))
@@ -408,7 +728,7 @@ mod tests {
         stream.extend(vec![0; pad_length as usize]);
         let suffix = b"Iknow";
         stream.extend(suffix);
-        let read = Index::read_entry(&stream);
+        let read = Index::read_entry(&stream, 2, "");
         assert_eq!(
             read,
             Ok((
@@ -417,9 +737,147 @@ mod tests {
                     "".to_string(),
                     DirEntry {
                         object_type: ObjectType::Regular,
-                        stat: FileStat { mtime: 0, size: 0 },
+                        stat: FileStat {
+                            mtime: 0,
+                            size: 0,
+                            executable: false,
+                            kind: FileKind::Regular,
+                            link_target: None,
+                        },
                         sha: *sha,
-                        name: "22".to_string()
+                        name: "22".to_string(),
+                        stage: 0,
+                        assume_valid: false,
+                        skip_worktree: false,
+                        intent_to_add: false,
+                    }
+                )
+            ))
+        );
+    }
+
+    #[test]
+    fn test_read_of_version_3_entry_with_extended_flags() {
+        let name = b"file.txt";
+        let sha = b"ab7ca9aba437ae8e3f8a";
+        let mut stream: Vec<u8> = vec![0; 40];
+        stream.extend(sha);
+        // Base flags: extended bit set (0x4000), stage 0, name length 8.
+        let flags: u16 = 0x4000 | (name.len() as u16);
+        stream.extend(&flags.to_be_bytes());
+        // Extended flags: skip-worktree (0x4000) and intent-to-add (0x2000) both set.
+        let extended_flags: u16 = 0x4000 | 0x2000;
+        stream.extend(&extended_flags.to_be_bytes());
+        stream.extend(name);
+        let pad_length = 8;
+        stream.extend(vec![0; pad_length as usize]);
+        let suffix = b"Iknow";
+        stream.extend(suffix);
+        let read = Index::read_entry(&stream, 3, "");
+        assert_eq!(
+            read,
+            Ok((
+                &suffix[..],
+                (
+                    "".to_string(),
+                    DirEntry {
+                        object_type: ObjectType::Regular,
+                        stat: FileStat {
+                            mtime: 0,
+                            size: 0,
+                            executable: false,
+                            kind: FileKind::Regular,
+                            link_target: None,
+                        },
+                        sha: *sha,
+                        name: "file.txt".to_string(),
+                        stage: 0,
+                        assume_valid: false,
+                        skip_worktree: true,
+                        intent_to_add: true,
+                    }
+                )
+            ))
+        );
+    }
+
+    #[test]
+    fn test_read_of_version_4_entry_reuses_previous_path_prefix() {
+        let previous_path = "some/deeply/nested/file_one.txt";
+        let sha = b"ab7ca9aba237a18e3f8a";
+        let mut stream: Vec<u8> = vec![0; 40];
+        stream.extend(sha);
+        // No change to the flags/name-length field is needed in version 4; the name is rebuilt
+        // from the varint + suffix that follow instead.
+        stream.extend(&0u16.to_be_bytes());
+        // Strip "file_one.txt" (12 bytes) from the previous path and append the new suffix.
+        stream.push(12);
+        stream.extend(b"file_two.txt\0");
+        let suffix = b"trailing bytes";
+        stream.extend(suffix);
+
+        let read = Index::read_entry(&stream, 4, previous_path);
+        assert_eq!(
+            read,
+            Ok((
+                &suffix[..],
+                (
+                    "some/deeply/nested".to_string(),
+                    DirEntry {
+                        object_type: ObjectType::Regular,
+                        stat: FileStat {
+                            mtime: 0,
+                            size: 0,
+                            executable: false,
+                            kind: FileKind::Regular,
+                            link_target: None,
+                        },
+                        sha: *sha,
+                        name: "file_two.txt".to_string(),
+                        stage: 0,
+                        assume_valid: false,
+                        skip_worktree: false,
+                        intent_to_add: false,
+                    }
+                )
+            ))
+        );
+    }
+
+    #[test]
+    fn test_read_of_version_4_entry_with_multi_byte_varint() {
+        let previous_path = "x".repeat(128);
+        let sha = b"ab7ca9aba237a18e3f8a";
+        let mut stream: Vec<u8> = vec![0; 40];
+        stream.extend(sha);
+        stream.extend(&0u16.to_be_bytes());
+        // A two-byte varint encoding of 128, stripping the entire previous path:
+        // [0x80, 0x00] => value = ((0 + 1) << 7) | 0 = 128.
+        stream.extend(&[0x80, 0x00]);
+        stream.extend(b"brand_new_name\0");
+
+        let read = Index::read_entry(&stream, 4, &previous_path);
+        assert_eq!(
+            read,
+            Ok((
+                &b""[..],
+                (
+                    "".to_string(),
+                    DirEntry {
+                        object_type: ObjectType::Regular,
+                        stat: FileStat {
+                            mtime: 0,
+                            size: 0,
+                            executable: false,
+                            kind: FileKind::Regular,
+                            link_target: None,
+                        },
+                        sha: *sha,
+                        name: "brand_new_name".to_string(),
+                        stage: 0,
+                        assume_valid: false,
+                        skip_worktree: false,
+                        intent_to_add: false,
                     }
                 )
             ))
@@ -457,7 +915,14 @@ mod tests {
         stream.extend(b"DIRC");
         stream.extend(&version.to_be_bytes());
         stream.extend(&entries.to_be_bytes());
-        for entry in 0..entries {
+
+        //The different stage numbers are not really used during git-add command. They are used for handling merge conflicts. In a nutshell:
+        //Slot 0: “normal”, un-conflicted, all-is-well entry.
+        //Slot 1: “base”, the common ancestor version.
+        //Slot 2: “ours”, the target (HEAD) version.
+        //Slot 3: “theirs”, the being-merged-in version.
+        let stages = [2u16, 3u16];
+        for &stage in &stages {
             let ctime: u64 = 10;
             stream.extend(&ctime.to_be_bytes());
             let mtime_s: u32 = 20;
@@ -478,26 +943,235 @@ mod tests {
             stream.extend(&file_size.to_be_bytes());
             stream.extend(sha);
             let mut name_length: u16 = name.len() as u16;
-            //The different stage numbers are not really used during git-add command. They are used for handling merge conflicts. In a nutshell:
-            //Slot 0: “normal”, un-conflicted, all-is-well entry.
-            //Slot 1: “base”, the common ancestor version.
-            //Slot 2: “ours”, the target (HEAD) version.
-            //Slot 3: “theirs”, the being-merged-in version.
-            let stage = match entry {
-                0 => 0,
-                _ => 0b0100000000000000,
-            };
-            name_length |= stage;
+            // Stage occupies bits 13-12 of the flags field.
+            name_length |= stage << 12;
             stream.extend(&name_length.to_be_bytes());
             stream.extend(name);
             let pad_length = 8 - ((62 + name_length) % 8);
             stream.extend(vec![0; pad_length as usize]);
         }
+        append_checksum(&mut stream);
         let index_file = temp_dir.join("some_index");
         fs::write(&index_file, stream).unwrap();
         let index = Index::new(&index_file).unwrap();
         let root = index.entries.get("").unwrap();
 
         assert_eq!(root.len(), 2);
+        let mut found_stages: Vec<u8> = root.iter().map(|e| e.stage).collect();
+        found_stages.sort_unstable();
+        assert_eq!(found_stages, vec![2, 3]);
+
+        let unmerged = index.unmerged_entries();
+        assert_eq!(unmerged.len(), 1);
+        assert_eq!(unmerged.get("some_file").unwrap().len(), 2);
+    }
+
+    // Builds the body of a single "TREE" extension record: a path, the entry/subtree counts, and
+    // (unless invalidated) an oid.
+    fn cache_tree_record(path: &str, entry_count: i32, subtree_count: u32, oid: Option<&[u8]>) -> Vec<u8> {
+        let mut record: Vec<u8> = vec![];
+        record.extend(path.as_bytes());
+        record.push(0);
+        record.extend(format!("{} {}\n", entry_count, subtree_count).as_bytes());
+        if let Some(oid) = oid {
+            record.extend(oid);
+        }
+        record
+    }
+
+    fn extension(signature: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut extension: Vec<u8> = vec![];
+        extension.extend(signature);
+        extension.extend(&(body.len() as u32).to_be_bytes());
+        extension.extend(body);
+        extension
+    }
+
+    // Appends the trailing checksum `Index::new` expects over everything written so far, so
+    // hand-built test streams load the same way a real index file would.
+    fn append_checksum(stream: &mut Vec<u8>) {
+        let digest: [u8; 20] = Sha1::digest(&stream[..]).into();
+        stream.extend(digest);
+    }
+
+    #[test]
+    fn test_oid_defaults_to_zero_without_a_tree_extension() {
+        let version: u32 = 2;
+        let entries: u32 = 0;
+        let mut stream: Vec<u8> = vec![];
+        stream.extend(b"DIRC");
+        stream.extend(&version.to_be_bytes());
+        stream.extend(&entries.to_be_bytes());
+        append_checksum(&mut stream);
+
+        let temp_dir = TempDir::default();
+        let index_file = temp_dir.join("some_index");
+        fs::write(&index_file, stream).unwrap();
+        let index = Index::new(&index_file).unwrap();
+
+        assert_eq!(index.oid(), &[0; 20]);
+    }
+
+    #[test]
+    fn test_oid_comes_from_the_tree_extension_root_record() {
+        let root_oid = b"root_oid_of_20_byte_";
+        let version: u32 = 2;
+        let entries: u32 = 0;
+        let mut stream: Vec<u8> = vec![];
+        stream.extend(b"DIRC");
+        stream.extend(&version.to_be_bytes());
+        stream.extend(&entries.to_be_bytes());
+
+        let body = cache_tree_record("", 0, 0, Some(root_oid));
+        stream.extend(extension(b"TREE", &body));
+        append_checksum(&mut stream);
+
+        let temp_dir = TempDir::default();
+        let index_file = temp_dir.join("some_index");
+        fs::write(&index_file, stream).unwrap();
+        let index = Index::new(&index_file).unwrap();
+
+        assert_eq!(index.oid(), root_oid);
+    }
+
+    #[test]
+    fn test_tree_extension_with_invalidated_subtree_has_no_oid() {
+        let root_oid = b"root_oid_of_20_byte_";
+        let version: u32 = 2;
+        let entries: u32 = 0;
+        let mut stream: Vec<u8> = vec![];
+        stream.extend(b"DIRC");
+        stream.extend(&version.to_be_bytes());
+        stream.extend(&entries.to_be_bytes());
+
+        let mut body = cache_tree_record("", 1, 1, Some(root_oid));
+        body.extend(cache_tree_record("subdir", -1, 0, None));
+        stream.extend(extension(b"TREE", &body));
+        append_checksum(&mut stream);
+
+        let temp_dir = TempDir::default();
+        let index_file = temp_dir.join("some_index");
+        fs::write(&index_file, stream).unwrap();
+        let index = Index::new(&index_file).unwrap();
+
+        assert_eq!(index.oid(), root_oid);
+        assert_eq!(index.cache_tree.get("subdir"), None);
+    }
+
+    #[test]
+    fn test_tree_extension_records_are_keyed_by_their_full_path_not_just_their_own_name() {
+        // Pre-order: root (1 child: "one"), "one" (1 child: "nested"), "nested" (1 child: "a"),
+        // "a" (0 children) - the same nesting the `one/nested/a/bit.txt` fixture in repo_status.rs
+        // produces, where every record but the root's path field is just its own name.
+        let root_oid = b"root_oid_of_20_bytes";
+        let one_oid = b"one_oid_of_20_bytes_";
+        let nested_oid = b"nested_oid_of_20_byt";
+        let a_oid = b"a_oid_of_20_bytes___";
+        let version: u32 = 2;
+        let entries: u32 = 0;
+        let mut stream: Vec<u8> = vec![];
+        stream.extend(b"DIRC");
+        stream.extend(&version.to_be_bytes());
+        stream.extend(&entries.to_be_bytes());
+
+        let mut body = cache_tree_record("", 1, 1, Some(root_oid));
+        body.extend(cache_tree_record("one", 1, 1, Some(one_oid)));
+        body.extend(cache_tree_record("nested", 1, 1, Some(nested_oid)));
+        body.extend(cache_tree_record("a", 1, 0, Some(a_oid)));
+        stream.extend(extension(b"TREE", &body));
+        append_checksum(&mut stream);
+
+        let temp_dir = TempDir::default();
+        let index_file = temp_dir.join("some_index");
+        fs::write(&index_file, stream).unwrap();
+        let index = Index::new(&index_file).unwrap();
+
+        assert_eq!(index.cache_tree.get("one"), Some(one_oid));
+        assert_eq!(index.cache_tree.get("one/nested"), Some(nested_oid));
+        assert_eq!(index.cache_tree.get("one/nested/a"), Some(a_oid));
+        assert_eq!(index.cache_tree.get("nested"), None);
+        assert_eq!(index.cache_tree.get("a"), None);
+    }
+
+    #[test]
+    fn test_tree_extension_does_not_clobber_sibling_directories_that_share_a_name() {
+        // Two unrelated directories ("alpha/shared" and "beta/shared") whose last path
+        // component happens to match must not collide in the cache_tree map.
+        let alpha_shared_oid = b"alpha_shared_oid_20b";
+        let beta_shared_oid = b"beta_shared_oid_20by";
+        let version: u32 = 2;
+        let entries: u32 = 0;
+        let mut stream: Vec<u8> = vec![];
+        stream.extend(b"DIRC");
+        stream.extend(&version.to_be_bytes());
+        stream.extend(&entries.to_be_bytes());
+
+        let mut body = cache_tree_record("", 2, 2, Some(b"root_oid_of_20_bytes"));
+        body.extend(cache_tree_record("alpha", 1, 1, Some(b"alpha_oid_of_20_byte")));
+        body.extend(cache_tree_record("shared", 1, 0, Some(alpha_shared_oid)));
+        body.extend(cache_tree_record("beta", 1, 1, Some(b"beta_oid_of_20_bytes")));
+        body.extend(cache_tree_record("shared", 1, 0, Some(beta_shared_oid)));
+        stream.extend(extension(b"TREE", &body));
+        append_checksum(&mut stream);
+
+        let temp_dir = TempDir::default();
+        let index_file = temp_dir.join("some_index");
+        fs::write(&index_file, stream).unwrap();
+        let index = Index::new(&index_file).unwrap();
+
+        assert_eq!(index.cache_tree.get("alpha/shared"), Some(alpha_shared_oid));
+        assert_eq!(index.cache_tree.get("beta/shared"), Some(beta_shared_oid));
+    }
+
+    #[test]
+    fn test_unknown_extension_is_skipped() {
+        let root_oid = b"root_oid_of_20_byte_";
+        let version: u32 = 2;
+        let entries: u32 = 0;
+        let mut stream: Vec<u8> = vec![];
+        stream.extend(b"DIRC");
+        stream.extend(&version.to_be_bytes());
+        stream.extend(&entries.to_be_bytes());
+
+        stream.extend(extension(b"UNKN", b"whatever bytes this extension wants"));
+        let body = cache_tree_record("", 0, 0, Some(root_oid));
+        stream.extend(extension(b"TREE", &body));
+        append_checksum(&mut stream);
+
+        let temp_dir = TempDir::default();
+        let index_file = temp_dir.join("some_index");
+        fs::write(&index_file, stream).unwrap();
+        let index = Index::new(&index_file).unwrap();
+
+        assert_eq!(index.oid(), root_oid);
+    }
+
+    #[test]
+    fn test_entries_matching_only_returns_entries_selected_by_the_pathspec() {
+        let mut entries = HashMap::new();
+        entries.insert(
+            "src".to_string(),
+            vec![DirEntry {
+                name: "index.rs".to_string(),
+                ..Default::default()
+            }],
+        );
+        entries.insert(
+            "docs".to_string(),
+            vec![DirEntry {
+                name: "readme.md".to_string(),
+                ..Default::default()
+            }],
+        );
+        let index = Index {
+            entries,
+            ..Default::default()
+        };
+
+        let pathspec = Pathspec::new(&["*.rs"]).unwrap();
+        let matching = index.entries_matching(&pathspec);
+
+        assert_eq!(matching.len(), 1);
+        assert!(matching.contains_key("src/index.rs"));
     }
 }
@@ -6,16 +6,24 @@
  */
 mod direntry;
 mod dirstat;
+mod dirtree;
 mod error;
+pub mod fsmonitor;
 mod index;
+pub mod pathspec;
 mod repo_status;
 pub mod status;
 mod tree;
+pub mod watcher;
 pub mod worktree;
 
 pub use direntry::DirEntry;
+pub use dirtree::DirTreeDiff;
 pub use error::StatusError;
+pub use fsmonitor::FsmonitorKind;
 pub use index::Index;
-pub use repo_status::RepoStatus;
+pub use pathspec::Pathspec;
+pub use repo_status::{RepoOperation, RepoStatus, StatusReport};
 pub use tree::TreeDiff;
-pub use worktree::WorkTree;
+pub use watcher::Watcher;
+pub use worktree::{UntrackedMode, WorkTree};
@@ -3,6 +3,7 @@ use std::{env, process};
 use termcolor::{ColorChoice, StandardStream};
 use win_git_status::RepoStatus;
 use win_git_status::StatusError;
+use win_git_status::UntrackedMode;
 
 fn run() -> Result<(), StatusError> {
     let matches = App::new("Win-git-status")
@@ -15,13 +16,87 @@ fn run() -> Result<(), StatusError> {
                 .takes_value(false)
                 .help("Give the output in the short-format."),
         )
+        .arg(
+            Arg::with_name("porcelain")
+                .long("porcelain")
+                .takes_value(true)
+                .possible_value("v2")
+                .min_values(0)
+                .default_value_if("porcelain", None, "v2")
+                .help("Give the output in a stable, machine-readable format. Only \"v2\" is supported."),
+        )
+        .arg(
+            Arg::with_name("null")
+                .short("z")
+                .takes_value(false)
+                .requires("porcelain")
+                .help("Terminate porcelain v2 records with NUL instead of LF."),
+        )
+        .arg(
+            Arg::with_name("branch")
+                .long("branch")
+                .takes_value(false)
+                .requires("short")
+                .help("Show the branch and tracking info, even in short-format."),
+        )
+        .arg(
+            Arg::with_name("untracked-files")
+                .short("u")
+                .long("untracked-files")
+                .takes_value(true)
+                .possible_values(&["no", "normal", "all"])
+                .min_values(0)
+                .default_value_if("untracked-files", None, "all")
+                .help(
+                    "The handling of untracked files; \"no\", \"normal\", or \"all\". \
+                     Defaults to \"all\" when given with no mode, otherwise falls back to \
+                     the \"status.showUntrackedFiles\" config value.",
+                ),
+        )
+        .arg(
+            Arg::with_name("find-renames")
+                .short("M")
+                .long("find-renames")
+                .takes_value(true)
+                .min_values(0)
+                .default_value_if("find-renames", None, "50")
+                .help(
+                    "Similarity percentage (0-100) a deletion and an addition must meet to be \
+                     reported as a rename instead of a delete/add pair. Defaults to 50 when \
+                     given with no value, otherwise falls back to the \"status.renames\" config \
+                     value.",
+                ),
+        )
+        .arg(
+            Arg::with_name("pathspec")
+                .multiple(true)
+                .help("Only show status for paths matching these patterns."),
+        )
         .get_matches();
 
     let path = env::current_dir()?;
-    let status = RepoStatus::new(&path)?;
+    let untracked_mode = match matches.value_of("untracked-files") {
+        Some("no") => Some(UntrackedMode::No),
+        Some("normal") => Some(UntrackedMode::Normal),
+        Some("all") => Some(UntrackedMode::All),
+        _ => None,
+    };
+    let pathspecs: Vec<&str> = matches
+        .values_of("pathspec")
+        .map(|values| values.collect())
+        .unwrap_or_default();
+    let rename_threshold = matches
+        .value_of("find-renames")
+        .map(|value| value.parse().unwrap_or(50));
+    let status = RepoStatus::new_with_options(&path, untracked_mode, &pathspecs, rename_threshold)?;
+    for (path, err) in status.errors() {
+        eprintln!("warning: {}: {}", path.display(), err.message);
+    }
     let mut stdout = StandardStream::stdout(ColorChoice::Auto);
-    if matches.is_present("short") {
-        status.write_short_message(&mut stdout)?;
+    if matches.is_present("porcelain") {
+        status.write_porcelain_v2_message(&mut stdout, matches.is_present("null"))?;
+    } else if matches.is_present("short") {
+        status.write_short_message(&mut stdout, matches.is_present("branch"))?;
     } else {
         status.write_long_message(&mut stdout)?;
     }
@@ -0,0 +1,86 @@
+/*
+ *          Copyright Nick G. 2021.
+ * Distributed under the Boost Software License, Version 1.0.
+ *    (See accompanying file LICENSE or copy at
+ *          https://www.boost.org/LICENSE_1_0.txt)
+ */
+
+// A pathspec engine for restricting a status run to particular paths or globs.
+//
+// Patterns use the same glob dialect as `.gitignore`: a leading `/` anchors a pattern to the
+// repo root, `**` matches across directory boundaries, a trailing `/` matches directories only,
+// and a leading `!` (or git's long-form `:(exclude)`) excludes paths that would otherwise match.
+// The `ignore` crate's gitignore matcher already implements this dialect, so this wraps that
+// rather than hand-rolling a second copy of it.
+
+use crate::error::StatusError;
+use ignore::gitignore::{Gitignore, GitignoreBuilder};
+
+impl From<ignore::Error> for StatusError {
+    fn from(err: ignore::Error) -> StatusError {
+        StatusError {
+            message: err.to_string(),
+        }
+    }
+}
+
+/// A compiled set of pathspec patterns, used to restrict which index entries or working-tree
+/// files a status run considers.
+pub struct Pathspec {
+    matcher: Gitignore,
+}
+
+impl Pathspec {
+    /// Compiles `patterns`, e.g. `["src/", "*.rs", ":(exclude)target/"]`, into a matcher.
+    pub fn new(patterns: &[&str]) -> Result<Pathspec, StatusError> {
+        let mut builder = GitignoreBuilder::new("");
+        for pattern in patterns {
+            // git's long-form `:(exclude)foo` is equivalent to gitignore's `!foo` negation.
+            let line = match pattern.strip_prefix(":(exclude)") {
+                Some(rest) => format!("!{}", rest),
+                None => (*pattern).to_string(),
+            };
+            builder.add_line(None, &line)?;
+        }
+        let matcher = builder.build()?;
+        Ok(Pathspec { matcher })
+    }
+
+    /// Returns whether `path`, relative to the repo root, is selected by this pathspec.
+    pub fn matches(&self, path: &str, is_dir: bool) -> bool {
+        self.matcher.matched_path_or_any_parents(path, is_dir).is_ignore()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_glob_pattern_matches_file_with_extension() {
+        let pathspec = Pathspec::new(&["*.rs"]).unwrap();
+        assert!(pathspec.matches("src/index.rs", false));
+        assert!(!pathspec.matches("src/index.txt", false));
+    }
+
+    #[test]
+    fn test_trailing_slash_only_matches_directories() {
+        let pathspec = Pathspec::new(&["target/"]).unwrap();
+        assert!(pathspec.matches("target", true));
+        assert!(!pathspec.matches("target", false));
+    }
+
+    #[test]
+    fn test_exclude_pattern_overrides_an_earlier_match() {
+        let pathspec = Pathspec::new(&["src/**", ":(exclude)src/generated.rs"]).unwrap();
+        assert!(pathspec.matches("src/index.rs", false));
+        assert!(!pathspec.matches("src/generated.rs", false));
+    }
+
+    #[test]
+    fn test_rooted_pattern_only_matches_at_the_repo_root() {
+        let pathspec = Pathspec::new(&["/README.md"]).unwrap();
+        assert!(pathspec.matches("README.md", false));
+        assert!(!pathspec.matches("docs/README.md", false));
+    }
+}
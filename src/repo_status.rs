@@ -6,15 +6,18 @@
  */
 
 use crate::error::StatusError;
-use crate::status::{Status, StatusEntry};
-use crate::{Index, TreeDiff, WorkTree};
+use crate::status::{ConflictKind, Status, StatusEntry};
+use crate::{Index, Pathspec, TreeDiff, UntrackedMode, WorkTree};
 use git2::{Oid, Repository, RepositoryState};
 use indoc::formatdoc;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
 use std::fmt;
 use std::fmt::{Debug, Formatter};
 use std::io::Write;
-use std::path::Path;
-use termcolor::{Color, ColorSpec, WriteColor};
+use std::path::{Path, PathBuf};
+use termcolor::{Buffer, Color, ColorSpec, WriteColor};
 
 // See for the list of slots https://git-scm.com/docs/git-config#Documentation/git-config.txt-colorstatusltslotgt
 enum StatusColorSlot {
@@ -22,6 +25,12 @@ enum StatusColorSlot {
     Changed,
     Added,
     NoBranch,
+    Unmerged,
+    // Not one of git's own `color.status.*` slots; this crate reuses the same config lookup
+    // mechanism so `prompt_summary`'s branch/ahead-behind segment can be themed the same way.
+    Branch,
+    // Also not one of git's own slots; themes `prompt_summary`'s stash marker the same way.
+    Stash,
 }
 
 impl fmt::Display for StatusColorSlot {
@@ -31,6 +40,9 @@ impl fmt::Display for StatusColorSlot {
             StatusColorSlot::Changed => write!(f, "changed"),
             StatusColorSlot::Added => write!(f, "added"),
             StatusColorSlot::NoBranch => write!(f, "nobranch"),
+            StatusColorSlot::Unmerged => write!(f, "unmerged"),
+            StatusColorSlot::Branch => write!(f, "branch"),
+            StatusColorSlot::Stash => write!(f, "stash"),
         }
     }
 }
@@ -42,6 +54,9 @@ impl StatusColorSlot {
             StatusColorSlot::Changed => Color::Red,
             StatusColorSlot::Added => Color::Green,
             StatusColorSlot::NoBranch => Color::Red,
+            StatusColorSlot::Unmerged => Color::Red,
+            StatusColorSlot::Branch => Color::Green,
+            StatusColorSlot::Stash => Color::Blue,
         }
     }
 }
@@ -50,11 +65,62 @@ pub struct RepoStatus {
     repo: Repository,
     index_diff: TreeDiff,
     work_tree_diff: WorkTree,
+    unmerged: Vec<StatusEntry>,
+}
+
+/// A merge/rebase/cherry-pick/revert/bisect `write_long_message` found in progress, with the
+/// detail needed to render it the way `git status` does instead of refusing to run.
+#[derive(PartialEq, Eq, Debug, Clone, Serialize)]
+pub enum RepoOperation {
+    Merge,
+    Rebase {
+        onto: String,
+        step: usize,
+        total: usize,
+    },
+    CherryPick {
+        sha: String,
+    },
+    Revert {
+        sha: String,
+    },
+    Bisect {
+        branch: Option<String>,
+    },
+}
+
+/// A structured, serializable snapshot of a repo's status: everything the text renderers
+/// (`write_short_message`, `write_long_message`, `write_porcelain_v2_message`) format for a
+/// human, as plain data for scripting consumers instead.  Built via `RepoStatus::report`.
+#[derive(PartialEq, Debug, Clone, Serialize)]
+pub struct StatusReport {
+    /// Full sha of the commit `HEAD` points at.
+    pub head: String,
+    /// Short local branch name, e.g. `"main"`.  `None` when `HEAD` is detached.
+    pub branch: Option<String>,
+    /// Short upstream branch name, e.g. `"origin/main"`, if one is configured and still exists.
+    pub upstream: Option<String>,
+    pub ahead: usize,
+    pub behind: usize,
+    pub operation: Option<RepoOperation>,
+    pub staged: Vec<StatusEntry>,
+    pub unstaged: Vec<StatusEntry>,
+    pub untracked: Vec<StatusEntry>,
+    pub unmerged: Vec<StatusEntry>,
+    /// Paths that couldn't be statted, read, or opened as a submodule repo while walking the
+    /// worktree, as `(path, message)` pairs.  The other fields still reflect everything that
+    /// _could_ be compared; these are surfaced separately so a scripting consumer can decide
+    /// whether to treat them as fatal.
+    pub errors: Vec<(String, String)>,
 }
 
 impl Debug for RepoStatus {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}\n{:?}", self.index_diff, self.work_tree_diff)
+        write!(
+            f,
+            "{:?}\n{:?}\n{:?}",
+            self.index_diff, self.work_tree_diff, self.unmerged
+        )
     }
 }
 
@@ -70,6 +136,110 @@ impl RepoStatus {
     /// * `path` - The path to a git repo.  This logic will search up parent directories for
     ///     a git repo
     pub fn new(path: &Path) -> Result<RepoStatus, StatusError> {
+        RepoStatus::new_with_options(path, None, &[], None)
+    }
+
+    /// Like `new`, but `untracked_mode_override` takes precedence over the repo's own
+    /// `status.showUntrackedFiles` config, mirroring git's `-u`/`--untracked-files` flag.
+    ///
+    /// * `path` - The path to a git repo.  This logic will search up parent directories for
+    ///     a git repo
+    /// * `untracked_mode_override` - When `Some`, used instead of `status.showUntrackedFiles`
+    pub fn new_with_untracked_mode(
+        path: &Path,
+        untracked_mode_override: Option<UntrackedMode>,
+    ) -> Result<RepoStatus, StatusError> {
+        RepoStatus::new_with_options(path, untracked_mode_override, &[], None)
+    }
+
+    /// Like `new`, but restricts the staged, unstaged, and untracked categories to paths
+    /// selected by `pathspecs` (the same glob dialect `Pathspec` uses), mirroring git's own
+    /// pathspec-scoped status runs, e.g. `git status src/ *.rs`.
+    ///
+    /// * `path` - The path to a git repo.  This logic will search up parent directories for
+    ///     a git repo
+    /// * `pathspecs` - Patterns restricting which paths are reported; an empty slice reports
+    ///     everything, the same as `new`
+    pub fn new_with_pathspecs(path: &Path, pathspecs: &[&str]) -> Result<RepoStatus, StatusError> {
+        RepoStatus::new_with_options(path, None, pathspecs, None)
+    }
+
+    /// A staged-only status scan, without the parallel worktree walk `new` also pays for: see
+    /// `staged_subtree_unchanged` for why scoping to `path_prefix` is near-zero work on a deep,
+    /// unchanged tree. Returns one `"<code>  <path>"` line per staged entry, the
+    /// same rendering `write_short_staged_and_unstaged` uses for a staged-only entry's line.
+    ///
+    /// * `path` - The path to a git repo.  This logic will search up parent directories for
+    ///     a git repo
+    /// * `path_prefix` - Restricts the scan to this pathspec; `None` scans the whole repo
+    /// * `rename_threshold` - Similarity percentage (`0..=100`) overriding
+    ///     `TreeDiff::DEFAULT_RENAME_THRESHOLD`, mirroring git's `-M<n>` flag; `None` falls back
+    ///     to the repo's `status.renames` config, then the default
+    pub fn staged_statuses(
+        path: &Path,
+        path_prefix: Option<&str>,
+        rename_threshold: Option<u16>,
+    ) -> Vec<String> {
+        let repo = Repository::open(path).ok();
+        let rename_threshold = rename_threshold.or_else(|| {
+            repo.as_ref()
+                .map(|repo| RepoStatus::read_rename_threshold(repo))
+        });
+        if let Some(repo) = &repo {
+            if RepoStatus::staged_subtree_unchanged(repo, path_prefix) {
+                return vec![];
+            }
+        }
+        TreeDiff::diff_against_index_with_options(path, path_prefix, rename_threshold)
+            .entries
+            .iter()
+            .map(|entry| format!("{}  {}", entry.state.short_status_string(), entry.name))
+            .collect()
+    }
+
+    // `path_prefix` restricts the scan to one directory's subtree (`None`/`"".into()` means the
+    // whole repo). The index's "TREE" extension caches the oid the index's own contents would
+    // produce for that directory, so when it's still present and matches the same directory in
+    // HEAD's tree, nothing under it is staged and `diff_against_index_with_options` can be
+    // skipped entirely instead of paying for libgit2's own diff walk.
+    fn staged_subtree_unchanged(repo: &Repository, path_prefix: Option<&str>) -> bool {
+        let index_file = repo.path().join("index");
+        let index = match Index::new(&index_file) {
+            Ok(index) => index,
+            Err(_) => return false,
+        };
+        let directory = path_prefix.map(|p| p.trim_end_matches('/')).unwrap_or("");
+        let cached_oid = match index.cached_tree_oid(directory) {
+            Some(oid) => oid,
+            None => return false,
+        };
+        let head_tree = match repo.head().and_then(|head| head.peel_to_tree()) {
+            Ok(tree) => tree,
+            Err(_) => return false,
+        };
+        let head_oid = if directory.is_empty() {
+            head_tree.id()
+        } else {
+            match head_tree.get_path(Path::new(directory)) {
+                Ok(entry) => entry.id(),
+                Err(_) => return false,
+            }
+        };
+        cached_oid[..] == *head_oid.as_bytes()
+    }
+
+    /// Like `new`, but combines `new_with_untracked_mode`'s and `new_with_pathspecs`'s
+    /// parameters; the CLI uses this directly since it can be given both at once.
+    ///
+    /// * `rename_threshold_override` - When `Some`, used instead of the repo's own
+    ///     `status.renames` config, mirroring git's `-M<n>` flag; see
+    ///     `TreeDiff::diff_against_index_with_options`
+    pub fn new_with_options(
+        path: &Path,
+        untracked_mode_override: Option<UntrackedMode>,
+        pathspecs: &[&str],
+        rename_threshold_override: Option<u16>,
+    ) -> Result<RepoStatus, StatusError> {
         let repo: Repository;
         let discovery = Repository::discover(path);
         match discovery {
@@ -89,29 +259,205 @@ impl RepoStatus {
         let repo_path = repo.path();
         let index_file = repo_path.join("index");
         let index = Index::new(&*index_file)?;
+        let mut unmerged = RepoStatus::conflict_entries(&index);
         let workdir = repo.workdir().unwrap();
-        let (work_tree_diff, index_diff) = rayon::join(
-            || WorkTree::diff_against_index(workdir, index).unwrap(),
-            || TreeDiff::diff_against_index(path),
+        let untracked_mode =
+            untracked_mode_override.unwrap_or_else(|| RepoStatus::read_untracked_mode(&repo));
+        let rename_threshold =
+            rename_threshold_override.unwrap_or_else(|| RepoStatus::read_rename_threshold(&repo));
+        let (work_tree_diff_result, mut index_diff) = rayon::join(
+            || WorkTree::diff_against_index_with_untracked_mode(workdir, index, untracked_mode),
+            || TreeDiff::diff_against_index_with_options(path, None, Some(rename_threshold)),
         );
+        let mut work_tree_diff = work_tree_diff_result?;
+        if !pathspecs.is_empty() {
+            let pathspec = Pathspec::new(pathspecs)?;
+            let matches = |entry: &StatusEntry| pathspec.matches(&entry.name, entry.name.ends_with('/'));
+            index_diff.entries.retain(matches);
+            work_tree_diff.entries.retain(matches);
+            unmerged.retain(matches);
+        }
         Ok(RepoStatus {
             repo,
             index_diff,
             work_tree_diff,
+            unmerged,
+        })
+    }
+
+    // Reads git's `status.showUntrackedFiles` config key; defaults to `Normal` the same way git
+    // itself does when the key is unset or holds something unrecognized.
+    fn read_untracked_mode(repo: &Repository) -> UntrackedMode {
+        let config = match repo.config() {
+            Ok(config) => config,
+            Err(_) => return UntrackedMode::Normal,
+        };
+        match config.get_string("status.showUntrackedFiles") {
+            Ok(value) if value == "no" => UntrackedMode::No,
+            Ok(value) if value == "all" => UntrackedMode::All,
+            _ => UntrackedMode::Normal,
+        }
+    }
+
+    // Reads git's `status.renames` config key as a similarity percentage, mirroring `-M<n>`;
+    // falls back to `TreeDiff::DEFAULT_RENAME_THRESHOLD` when the key is unset or unparseable.
+    fn read_rename_threshold(repo: &Repository) -> u16 {
+        let config = match repo.config() {
+            Ok(config) => config,
+            Err(_) => return TreeDiff::DEFAULT_RENAME_THRESHOLD,
+        };
+        config
+            .get_i64("status.renames")
+            .ok()
+            .and_then(|value| u16::try_from(value).ok())
+            .unwrap_or(TreeDiff::DEFAULT_RENAME_THRESHOLD)
+    }
+
+    // The index drops the usual stage-0 entry for a conflicted path in favor of one entry per
+    // side that disagreed; `Index::unmerged_entries` groups those back together by path so they
+    // can be turned into a single `Status::Conflict` entry each.
+    fn conflict_entries(index: &Index) -> Vec<StatusEntry> {
+        let mut unmerged: Vec<StatusEntry> = index
+            .unmerged_entries()
+            .into_iter()
+            .filter_map(|(name, entries)| {
+                let stages: Vec<u8> = entries.iter().map(|e| e.stage).collect();
+                ConflictKind::from_stages(&stages).map(|kind| StatusEntry {
+                    name,
+                    state: Status::Conflict(kind),
+                })
+            })
+            .collect();
+        unmerged.sort_by(|a, b| a.name.cmp(&b.name));
+        unmerged
+    }
+
+    /// A structured snapshot of this status, for callers that want the data rather than one of
+    /// the pre-formatted text renderings.  Built from the same underlying queries those renderers
+    /// use, so it stays consistent with `write_short_message`/`write_long_message`.
+    pub fn report(&self) -> Result<StatusReport, StatusError> {
+        self.check_repo_state()?;
+
+        let head_commit = self.repo.head()?.peel_to_commit()?;
+        let branch = self
+            .branch_name()
+            .map(|name| name.strip_prefix("refs/heads/").unwrap().to_string());
+
+        let mut upstream = None;
+        let mut ahead = 0;
+        let mut behind = 0;
+        if let Some(upstream_name) = self.upstream_branch_name() {
+            if let Some(upstream_oid) = self.get_oid(&upstream_name) {
+                let (a, b) = self
+                    .repo
+                    .graph_ahead_behind(head_commit.id(), upstream_oid)?;
+                ahead = a;
+                behind = b;
+            }
+            upstream = Some(
+                upstream_name
+                    .strip_prefix("refs/remotes/")
+                    .unwrap()
+                    .to_string(),
+            );
+        }
+
+        let unstaged = self
+            .work_tree_diff
+            .entries
+            .iter()
+            .filter(|e| e.state != Status::New)
+            .cloned()
+            .collect();
+        let untracked = self
+            .work_tree_diff
+            .entries
+            .iter()
+            .filter(|e| e.state == Status::New)
+            .cloned()
+            .collect();
+
+        Ok(StatusReport {
+            head: head_commit.id().to_string(),
+            branch,
+            upstream,
+            ahead,
+            behind,
+            operation: self.detect_operation(),
+            staged: self.index_diff.entries.clone(),
+            unstaged,
+            untracked,
+            unmerged: self.unmerged.clone(),
+            errors: self
+                .work_tree_diff
+                .errors
+                .iter()
+                .map(|(path, err)| (path.to_string_lossy().to_string(), err.message.clone()))
+                .collect(),
         })
     }
 
+    /// Paths that couldn't be statted, read, or opened as a submodule repo while walking the
+    /// worktree.  See `WorkTree::errors` for details; exposed here so a caller doesn't need to
+    /// reach into `work_tree_diff` itself.
+    pub fn errors(&self) -> &[(PathBuf, StatusError)] {
+        &self.work_tree_diff.errors
+    }
+
+    /// Writes git's `--short` format: one `XY path` line per entry, where `X` is the staged
+    /// state and `Y` the worktree state.  `branch` mirrors git's own `--short --branch`: when
+    /// true, a `## branch...upstream [ahead N, behind M]` header line is written first.
     pub fn write_short_message<W: WriteColor + Write>(
         &self,
         writer: &mut W,
+        branch: bool,
     ) -> Result<(), StatusError> {
         self.check_repo_state()?;
-        self.write_short_staged(writer);
-        self.write_short_unstaged(writer);
+        if branch {
+            self.write_short_branch_header(writer)?;
+        }
+        self.write_short_staged_and_unstaged(writer);
+        self.write_short_unmerged(writer);
         self.write_short_untracked(writer);
         Ok(())
     }
 
+    // Mirrors `write_porcelain_v2_branch_headers`'s branch/upstream/ahead-behind logic, but
+    // collapsed onto the single `## ...` line git's `--short --branch` uses instead of
+    // porcelain v2's separate `# branch.*` records.
+    fn write_short_branch_header<W: Write>(&self, writer: &mut W) -> Result<(), StatusError> {
+        let head_line = match self.branch_name() {
+            Some(name) => name.strip_prefix("refs/heads/").unwrap().to_string(),
+            None => {
+                writer.write_all(b"## HEAD (no branch)\n")?;
+                return Ok(());
+            }
+        };
+
+        let mut line = format!("## {}", head_line);
+        if let Some(upstream) = self.upstream_branch_name() {
+            let short_upstream = upstream.strip_prefix("refs/remotes/").unwrap();
+            line.push_str(&format!("...{}", short_upstream));
+            if let Some(upstream_oid) = self.get_oid(&upstream) {
+                let head_oid = self.repo.head()?.peel_to_commit()?.id();
+                let (ahead, behind) = self.repo.graph_ahead_behind(head_oid, upstream_oid)?;
+                if ahead > 0 || behind > 0 {
+                    let mut parts = Vec::new();
+                    if ahead > 0 {
+                        parts.push(format!("ahead {}", ahead));
+                    }
+                    if behind > 0 {
+                        parts.push(format!("behind {}", behind));
+                    }
+                    line.push_str(&format!(" [{}]", parts.join(", ")));
+                }
+            }
+        }
+        writer.write_all(line.as_bytes())?;
+        writer.write_all(b"\n")?;
+        Ok(())
+    }
+
     pub fn write_long_message<W: WriteColor + Write>(
         &self,
         writer: &mut W,
@@ -119,13 +465,346 @@ impl RepoStatus {
         self.check_repo_state()?;
         self.write_branch_message(writer)?;
         self.write_remote_branch_difference_message(writer);
+        let operation = self.detect_operation();
+        if let Some(operation) = &operation {
+            RepoStatus::write_operation_message(writer, operation);
+        }
+        let unmerged = self.write_unmerged_message(writer);
         let staged = self.write_staged_message(writer);
         let unstaged = self.write_unstaged_message(writer);
         let untracked = self.write_untracked_message(writer);
-        RepoStatus::write_epilog(writer, staged, unstaged, untracked);
+        RepoStatus::write_epilog(
+            writer,
+            staged,
+            unstaged,
+            untracked,
+            operation.is_some() || unmerged,
+        );
+        Ok(())
+    }
+
+    /// Writes git's `--porcelain=v2 --branch` format: stable, machine-parseable branch headers
+    /// followed by one line per entry.  Unlike `write_short_message`/`write_long_message` this is
+    /// never colorized, regardless of `color.status` or the writer's own color support, since a
+    /// script parsing this output shouldn't have to strip ANSI codes to do it.
+    ///
+    /// # Arguments
+    /// * `nul_terminated` - Mirrors git's own `-z`: when true, every record ends in `\0` instead
+    ///   of `\n`, so a path containing a newline can't be mistaken for a record boundary.
+    pub fn write_porcelain_v2_message<W: WriteColor + Write>(
+        &self,
+        writer: &mut W,
+        nul_terminated: bool,
+    ) -> Result<(), StatusError> {
+        self.check_repo_state()?;
+        let terminator: &[u8] = if nul_terminated { b"\0" } else { b"\n" };
+        self.write_porcelain_v2_branch_headers(writer, terminator)?;
+        for (name, (staged, unstaged)) in self.combined_statuses() {
+            RepoStatus::write_porcelain_v2_entry(writer, name, staged, unstaged, terminator)?;
+        }
+        for file in &self.unmerged {
+            RepoStatus::write_porcelain_v2_unmerged_entry(writer, file, terminator)?;
+        }
+        for file in self
+            .work_tree_diff
+            .entries
+            .iter()
+            .filter(|e| e.state == Status::New)
+        {
+            writer.write_all(b"? ")?;
+            writer.write_all(file.name.as_bytes())?;
+            writer.write_all(terminator)?;
+        }
+        Ok(())
+    }
+
+    fn write_porcelain_v2_branch_headers<W: Write>(
+        &self,
+        writer: &mut W,
+        terminator: &[u8],
+    ) -> Result<(), StatusError> {
+        let head = self.repo.head()?;
+        let oid = head.peel_to_commit()?.id();
+        writer.write_all(format!("# branch.oid {}", oid).as_bytes())?;
+        writer.write_all(terminator)?;
+
+        let head_line = match self.branch_name() {
+            Some(name) => name.strip_prefix("refs/heads/").unwrap().to_string(),
+            None => "(detached)".to_string(),
+        };
+        writer.write_all(format!("# branch.head {}", head_line).as_bytes())?;
+        writer.write_all(terminator)?;
+
+        let upstream = match self.upstream_branch_name() {
+            Some(upstream) => upstream,
+            None => return Ok(()),
+        };
+        let short_upstream = upstream.strip_prefix("refs/remotes/").unwrap();
+        writer.write_all(format!("# branch.upstream {}", short_upstream).as_bytes())?;
+        writer.write_all(terminator)?;
+
+        if let Some(upstream_oid) = self.get_oid(&upstream) {
+            let (ahead, behind) = self.repo.graph_ahead_behind(oid, upstream_oid)?;
+            writer.write_all(format!("# branch.ab +{} -{}", ahead, behind).as_bytes())?;
+            writer.write_all(terminator)?;
+        }
+        Ok(())
+    }
+
+    // `StatusEntry`'s `Renamed`/`Copied` variants carry the old path but not a similarity score,
+    // so (like the mode/hash placeholders below) every rename/copy is reported at this
+    // placeholder score rather than its real computed similarity.
+    const RENAME_SCORE_PLACEHOLDER: u8 = 100;
+
+    // Mode and object-id tracking isn't threaded through `StatusEntry` yet, so every entry is
+    // reported with a regular-file mode and an all-zero hash, the same placeholders git itself
+    // falls back to for a path whose object id it hasn't computed.
+    fn write_porcelain_v2_entry<W: Write>(
+        writer: &mut W,
+        name: &str,
+        staged: Option<&StatusEntry>,
+        unstaged: Option<&StatusEntry>,
+        terminator: &[u8],
+    ) -> Result<(), StatusError> {
+        if let Some(file) = staged {
+            if let Status::Renamed(old_name) | Status::Copied(old_name) = &file.state {
+                return RepoStatus::write_porcelain_v2_rename_entry(
+                    writer, file, old_name, unstaged, terminator,
+                );
+            }
+        }
+        let x = staged.map_or('.', |e| RepoStatus::porcelain_v2_code(&e.state));
+        let y = unstaged.map_or('.', |e| RepoStatus::porcelain_v2_code(&e.state));
+        let sub = staged.or(unstaged).map_or_else(
+            || "N...".to_string(),
+            |e| RepoStatus::porcelain_v2_submodule_field(&e.state),
+        );
+        const NULL_OID: &str = "0000000000000000000000000000000000000000";
+
+        let line = format!(
+            "1 {}{} {} 100644 100644 100644 {} {} {}",
+            x, y, sub, NULL_OID, NULL_OID, name
+        );
+        writer.write_all(line.as_bytes())?;
+        writer.write_all(terminator)?;
+        Ok(())
+    }
+
+    // Git's porcelain v2 reports a rename/copy through its own `2` record: a similarity score
+    // and the old path, tab-separated from the new one (NUL-separated under `-z`), rather than
+    // the ordinary `1` record's single path.  `unstaged` is the same path's worktree-side entry
+    // (if any) - unstaged rename/copy detection isn't implemented (see `TreeDiff`'s doc), but a
+    // staged rename can still have an unrelated unstaged modification on top of it, so its code
+    // still belongs in the `Y` column here.
+    fn write_porcelain_v2_rename_entry<W: Write>(
+        writer: &mut W,
+        file: &StatusEntry,
+        old_name: &str,
+        unstaged: Option<&StatusEntry>,
+        terminator: &[u8],
+    ) -> Result<(), StatusError> {
+        let code = match file.state {
+            Status::Renamed(_) => 'R',
+            Status::Copied(_) => 'C',
+            _ => unreachable!("only called for Renamed/Copied entries"),
+        };
+        let y = unstaged.map_or('.', |e| RepoStatus::porcelain_v2_code(&e.state));
+        const NULL_OID: &str = "0000000000000000000000000000000000000000";
+
+        let line = format!(
+            "2 {}{} N... 100644 100644 100644 {} {} {}{} {}",
+            code,
+            y,
+            NULL_OID,
+            NULL_OID,
+            code,
+            RepoStatus::RENAME_SCORE_PLACEHOLDER,
+            file.name
+        );
+        writer.write_all(line.as_bytes())?;
+        let path_separator: &[u8] = if terminator == b"\0" { b"\0" } else { b"\t" };
+        writer.write_all(path_separator)?;
+        writer.write_all(old_name.as_bytes())?;
+        writer.write_all(terminator)?;
+        Ok(())
+    }
+
+    fn porcelain_v2_code(state: &Status) -> char {
+        match state {
+            Status::Current => '.',
+            Status::New => 'A',
+            Status::Modified(_) => 'M',
+            Status::TypeChange => 'T',
+            Status::Deleted => 'D',
+            // Renamed/Copied entries are routed to `write_porcelain_v2_rename_entry`'s own `2`
+            // record before this is ever reached; these arms exist only so the match stays
+            // exhaustive.
+            Status::Renamed(_) => 'R',
+            Status::Copied(_) => 'C',
+            // Conflicts are reported through their own `u` record, via
+            // `write_porcelain_v2_unmerged_entry`, not this ordinary `1` record's single code.
+            Status::Conflict(_) => '.',
+        }
+    }
+
+    // See `write_porcelain_v2_entry`'s note: no mode/hash tracking is available on `StatusEntry`
+    // yet, so this uses the same regular-file-mode, all-zero-hash placeholders for all three
+    // stages plus the worktree mode.
+    fn write_porcelain_v2_unmerged_entry<W: Write>(
+        writer: &mut W,
+        file: &StatusEntry,
+        terminator: &[u8],
+    ) -> Result<(), StatusError> {
+        let code = match &file.state {
+            Status::Conflict(kind) => kind.code(),
+            _ => return Ok(()),
+        };
+        const NULL_OID: &str = "0000000000000000000000000000000000000000";
+        let line = format!(
+            "u {} N... 100644 100644 100644 100644 {} {} {} {}",
+            code, NULL_OID, NULL_OID, NULL_OID, file.name
+        );
+        writer.write_all(line.as_bytes())?;
+        writer.write_all(terminator)?;
+        Ok(())
+    }
+
+    // Submodule entries fold their new-commits/modified/untracked flags into `Modified`'s
+    // message (see `submodule_spawned_status`); reparse that message back into the 4 character
+    // `<sub>` field git's porcelain v2 format uses, rather than a plain "N...".
+    fn porcelain_v2_submodule_field(state: &Status) -> String {
+        let message = match state {
+            Status::Modified(Some(message)) => message,
+            _ => return "N...".to_string(),
+        };
+        const REASONS: [&str; 3] = ["new commits", "modified content", "untracked content"];
+        if !REASONS.iter().any(|reason| message.contains(reason)) {
+            return "N...".to_string();
+        }
+        let commit_changed = if message.contains("new commits") { 'C' } else { '.' };
+        let modified = if message.contains("modified content") { 'M' } else { '.' };
+        let untracked = if message.contains("untracked content") { 'U' } else { '.' };
+        format!("S{}{}{}", commit_changed, modified, untracked)
+    }
+
+    /// A compact, single-line VCS summary suitable for a shell prompt: the branch name (or
+    /// 7-char detached sha), an ahead/behind indicator, and dirty-state flags.  Plain text with
+    /// no color codes; see `write_prompt_summary` for a colorized version driven by the same
+    /// `color.status.*` config this crate already reads.
+    pub fn prompt_summary(&self) -> Result<String, StatusError> {
+        let mut buffer = Buffer::no_color();
+        self.write_prompt_summary(&mut buffer)?;
+        Ok(String::from_utf8(buffer.into_inner()).unwrap())
+    }
+
+    pub fn write_prompt_summary<W: WriteColor + Write>(
+        &self,
+        writer: &mut W,
+    ) -> Result<(), StatusError> {
+        self.check_repo_state()?;
+
+        let mut color_spec = ColorSpec::new();
+        color_spec.set_fg(Some(self.get_color(StatusColorSlot::Branch)));
+        writer.set_color(&color_spec)?;
+        let head_oid = match self.branch_name() {
+            Some(name) => {
+                let short_name = name.strip_prefix("refs/heads/").unwrap();
+                writer.write_all(short_name.as_bytes())?;
+                None
+            }
+            None => {
+                let commit = self.repo.head()?.peel_to_commit()?;
+                writer.write_all(commit.id().to_string()[..7].as_bytes())?;
+                Some(commit.id())
+            }
+        };
+
+        if let Some(upstream) = self.upstream_branch_name() {
+            if let Some(upstream_oid) = self.get_oid(&upstream) {
+                let local_oid = match head_oid {
+                    Some(oid) => oid,
+                    None => self.repo.head()?.peel_to_commit()?.id(),
+                };
+                let (ahead, behind) = self.repo.graph_ahead_behind(local_oid, upstream_oid)?;
+                if ahead > 0 && behind > 0 {
+                    // Diverged from upstream; unlike the ahead/behind-only cases there's no
+                    // single commit count to show, so this symbol stands alone.
+                    writer.write_all("\u{21D5}".as_bytes())?;
+                } else if ahead > 0 {
+                    writer.write_all(format!("\u{21E1}{}", ahead).as_bytes())?;
+                } else if behind > 0 {
+                    writer.write_all(format!("\u{21E3}{}", behind).as_bytes())?;
+                }
+            }
+        }
+        writer.reset()?;
+
+        let conflicted = self.unmerged.len();
+        if conflicted > 0 {
+            let mut color_spec = ColorSpec::new();
+            color_spec.set_fg(Some(self.get_color(StatusColorSlot::Unmerged)));
+            writer.set_color(&color_spec)?;
+            writer.write_all(format!("\u{2716}{}", conflicted).as_bytes())?;
+            writer.reset()?;
+        }
+
+        let staged = self.index_diff.entries.len();
+        if staged > 0 {
+            let mut color_spec = ColorSpec::new();
+            color_spec.set_fg(Some(self.get_color(StatusColorSlot::Added)));
+            writer.set_color(&color_spec)?;
+            writer.write_all(format!("+{}", staged).as_bytes())?;
+            writer.reset()?;
+        }
+
+        let modified = self
+            .work_tree_diff
+            .entries
+            .iter()
+            .filter(|e| e.state != Status::New)
+            .count();
+        if modified > 0 {
+            let mut color_spec = ColorSpec::new();
+            color_spec.set_fg(Some(self.get_color(StatusColorSlot::Changed)));
+            writer.set_color(&color_spec)?;
+            writer.write_all(format!("!{}", modified).as_bytes())?;
+            writer.reset()?;
+        }
+
+        let untracked = self
+            .work_tree_diff
+            .entries
+            .iter()
+            .filter(|e| e.state == Status::New)
+            .count();
+        if untracked > 0 {
+            let mut color_spec = ColorSpec::new();
+            color_spec.set_fg(Some(self.get_color(StatusColorSlot::Untracked)));
+            writer.set_color(&color_spec)?;
+            writer.write_all(format!("?{}", untracked).as_bytes())?;
+            writer.reset()?;
+        }
+
+        let stash = self.stash_count();
+        if stash > 0 {
+            let mut color_spec = ColorSpec::new();
+            color_spec.set_fg(Some(self.get_color(StatusColorSlot::Stash)));
+            writer.set_color(&color_spec)?;
+            writer.write_all(format!("${}", stash).as_bytes())?;
+            writer.reset()?;
+        }
         Ok(())
     }
 
+    // Counts the entries in `refs/stash`'s reflog, the same source `git stash list` reads; a
+    // repo with no stashes simply has no such reflog.
+    fn stash_count(&self) -> usize {
+        self.repo
+            .reflog("refs/stash")
+            .map(|reflog| reflog.len())
+            .unwrap_or(0)
+    }
+
     fn get_color(&self, color_slot: StatusColorSlot) -> Color {
         let config = self.repo.config().unwrap();
         let config_string = format!("color.status.{}", color_slot);
@@ -372,11 +1051,33 @@ impl RepoStatus {
         true
     }
 
+    fn write_unmerged_message<W: WriteColor + Write>(&self, writer: &mut W) -> bool {
+        if self.unmerged.is_empty() {
+            return false;
+        }
+        let message = formatdoc! {"\
+            Unmerged paths:
+              (use \"git add <file>...\" to mark resolution)"};
+        writer.write_all(message.as_bytes()).unwrap();
+
+        let mut color_spec = ColorSpec::new();
+        color_spec.set_fg(Some(self.get_color(StatusColorSlot::Unmerged)));
+        writer.set_color(&color_spec).unwrap();
+        for file in &self.unmerged {
+            let line = format! {"\n        {}", file.to_string()};
+            writer.write_all(line.as_bytes()).unwrap();
+        }
+        writer.reset().unwrap();
+        writer.write_all(b"\n\n").unwrap();
+        true
+    }
+
     fn write_epilog<W: WriteColor + Write>(
         writer: &mut W,
         staged: bool,
         unstaged: bool,
         untracked: bool,
+        operation_in_progress: bool,
     ) {
         if staged {
             return;
@@ -393,24 +1094,35 @@ impl RepoStatus {
             writer.write_all(b"nothing added to commit but untracked files present (use \"git add\" to track)\n").unwrap();
             return;
         }
+        if operation_in_progress {
+            writer
+                .write_all(
+                    b"nothing to commit but conflicts present; fix conflicts and run \"git commit\"\n",
+                )
+                .unwrap();
+            return;
+        }
         writer
             .write_all(b"nothing to commit, working tree clean\n")
             .unwrap();
     }
 
+    // Only the states git itself can't make sense of without more plumbing (an in-progress
+    // sequence, or an am-style mailbox apply) are still refused outright; `Merge`, `Rebase*`,
+    // `CherryPick`, `Revert`, and `Bisect` are rendered by `detect_operation` instead.
     fn check_repo_state(&self) -> Result<(), StatusError> {
         let state = self.repo.state();
         let unsupported_state = match state {
-            RepositoryState::Clean => return Ok(()),
-            RepositoryState::Merge => "Merge",
-            RepositoryState::Revert => "Revert",
+            RepositoryState::Clean
+            | RepositoryState::Merge
+            | RepositoryState::Revert
+            | RepositoryState::CherryPick
+            | RepositoryState::Rebase
+            | RepositoryState::RebaseInteractive
+            | RepositoryState::RebaseMerge
+            | RepositoryState::Bisect => return Ok(()),
             RepositoryState::RevertSequence => "RevertSequence",
-            RepositoryState::CherryPick => "CherryPick",
             RepositoryState::CherryPickSequence => "CherryPickSequence",
-            RepositoryState::Bisect => "Bisect",
-            RepositoryState::Rebase => "Rebase",
-            RepositoryState::RebaseInteractive => "RebaseInteractive",
-            RepositoryState::RebaseMerge => "RebaseMerge",
             RepositoryState::ApplyMailbox => "ApplyMailbox",
             RepositoryState::ApplyMailboxOrRebase => "ApplyMailboxOrRebase",
         };
@@ -421,44 +1133,140 @@ impl RepoStatus {
             ),
         })
     }
-    fn write_short_staged<W: WriteColor + Write>(&self, writer: &mut W) {
-        if self.index_diff.entries.is_empty() {
-            return;
-        }
 
-        let mut color_spec = ColorSpec::new();
-        let staged_color = Some(self.get_color(StatusColorSlot::Added));
-        color_spec.set_fg(staged_color);
-        for file in &self.index_diff.entries {
-            writer.set_color(&color_spec).unwrap();
-            writer
-                .write_all(file.state.short_status_string().as_bytes())
-                .unwrap();
-            writer.write_all(b"  ").unwrap();
-            writer.reset().unwrap();
-            writer.write_all(file.name.as_bytes()).unwrap();
-            writer.write_all(b"\n").unwrap();
+    fn detect_operation(&self) -> Option<RepoOperation> {
+        match self.repo.state() {
+            RepositoryState::Merge => Some(RepoOperation::Merge),
+            RepositoryState::Rebase
+            | RepositoryState::RebaseInteractive
+            | RepositoryState::RebaseMerge => self.detect_rebase_operation(),
+            RepositoryState::CherryPick => self
+                .read_operation_head("CHERRY_PICK_HEAD")
+                .map(|sha| RepoOperation::CherryPick { sha }),
+            RepositoryState::Revert => self
+                .read_operation_head("REVERT_HEAD")
+                .map(|sha| RepoOperation::Revert { sha }),
+            RepositoryState::Bisect => Some(RepoOperation::Bisect {
+                branch: self.read_operation_head("BISECT_START"),
+            }),
+            _ => None,
         }
     }
-    fn write_short_unstaged<W: WriteColor + Write>(&self, writer: &mut W) {
-        let unstaged_files: Vec<&StatusEntry> = self
+
+    // `.git/rebase-merge` holds an interactive (or merge-based) rebase's state, while
+    // `.git/rebase-apply` holds an am-style one; the two use different file names for the same
+    // "which step are we on" bookkeeping.
+    fn detect_rebase_operation(&self) -> Option<RepoOperation> {
+        let git_dir = self.repo.path();
+        let (rebase_dir, step_file, total_file) = if git_dir.join("rebase-merge").is_dir() {
+            (git_dir.join("rebase-merge"), "msgnum", "end")
+        } else {
+            (git_dir.join("rebase-apply"), "next", "last")
+        };
+
+        let onto = std::fs::read_to_string(rebase_dir.join("onto"))
+            .ok()?
+            .trim()
+            .to_string();
+        let step = RepoStatus::read_usize(&rebase_dir.join(step_file)).unwrap_or(0);
+        let total = RepoStatus::read_usize(&rebase_dir.join(total_file)).unwrap_or(0);
+
+        Some(RepoOperation::Rebase { onto, step, total })
+    }
+
+    fn read_operation_head(&self, file_name: &str) -> Option<String> {
+        let contents = std::fs::read_to_string(self.repo.path().join(file_name)).ok()?;
+        Some(contents.trim().to_string())
+    }
+
+    fn read_usize(path: &Path) -> Option<usize> {
+        std::fs::read_to_string(path).ok()?.trim().parse().ok()
+    }
+
+    fn write_operation_message<W: WriteColor + Write>(writer: &mut W, operation: &RepoOperation) {
+        let message = match operation {
+            RepoOperation::Merge => formatdoc! {"\
+                You have unmerged paths.
+                  (fix conflicts and run \"git commit\")"},
+            RepoOperation::Rebase { onto, step, total } => formatdoc! {"\
+                interactive rebase in progress; onto {onto}
+                Last command done ({step}/{total}):",
+                onto = onto, step = step, total = total},
+            RepoOperation::CherryPick { sha } => {
+                format!("You are currently cherry-picking commit {}.", sha)
+            }
+            RepoOperation::Revert { sha } => {
+                format!("You are currently reverting commit {}.", sha)
+            }
+            RepoOperation::Bisect { branch } => match branch {
+                Some(branch) => format!(
+                    "You are currently bisecting, started from branch '{}'.",
+                    branch
+                ),
+                None => "You are currently bisecting.".to_string(),
+            },
+        };
+        writer.write_all(message.as_bytes()).unwrap();
+        writer.write_all(b"\n\n").unwrap();
+    }
+    // A path can be both staged-modified and worktree-modified at once (stage a change, then
+    // edit again), and `--short`'s `XY path` format reports that as a single line with X from
+    // the staged side and Y from the worktree side, not two separate lines - so this merges
+    // `index_diff` and `work_tree_diff` by path before either renderer below looks at them.
+    // `BTreeMap` also gives both renderers a stable, path-sorted iteration order for free.
+    fn combined_statuses(&self) -> BTreeMap<&str, (Option<&StatusEntry>, Option<&StatusEntry>)> {
+        let mut combined: BTreeMap<&str, (Option<&StatusEntry>, Option<&StatusEntry>)> =
+            BTreeMap::new();
+        for entry in &self.index_diff.entries {
+            combined.entry(&entry.name).or_insert((None, None)).0 = Some(entry);
+        }
+        for entry in self
             .work_tree_diff
             .entries
             .iter()
             .filter(|e| e.state != Status::New)
-            .collect();
-        if unstaged_files.is_empty() {
+        {
+            combined.entry(&entry.name).or_insert((None, None)).1 = Some(entry);
+        }
+        combined
+    }
+
+    fn write_short_staged_and_unstaged<W: WriteColor + Write>(&self, writer: &mut W) {
+        let combined = self.combined_statuses();
+        if combined.is_empty() {
+            return;
+        }
+        let mut staged_spec = ColorSpec::new();
+        staged_spec.set_fg(Some(self.get_color(StatusColorSlot::Added)));
+        let mut unstaged_spec = ColorSpec::new();
+        unstaged_spec.set_fg(Some(self.get_color(StatusColorSlot::Changed)));
+        for (name, (staged, unstaged)) in combined {
+            let x = staged.map_or(" ", |e| e.state.short_status_string());
+            let y = unstaged.map_or(" ", |e| e.state.short_status_string());
+            writer.set_color(&staged_spec).unwrap();
+            writer.write_all(x.as_bytes()).unwrap();
+            writer.set_color(&unstaged_spec).unwrap();
+            writer.write_all(y.as_bytes()).unwrap();
+            writer.reset().unwrap();
+            writer.write_all(b" ").unwrap();
+            writer.write_all(name.as_bytes()).unwrap();
+            writer.write_all(b"\n").unwrap();
+        }
+    }
+
+    fn write_short_unmerged<W: WriteColor + Write>(&self, writer: &mut W) {
+        if self.unmerged.is_empty() {
             return;
         }
         let mut color_spec = ColorSpec::new();
-        let unstaged_color = Some(self.get_color(StatusColorSlot::Changed));
-        color_spec.set_fg(unstaged_color);
-        for file in unstaged_files {
+        color_spec.set_fg(Some(self.get_color(StatusColorSlot::Unmerged)));
+        for file in &self.unmerged {
+            let code = match &file.state {
+                Status::Conflict(kind) => kind.code(),
+                _ => continue,
+            };
             writer.set_color(&color_spec).unwrap();
-            writer.write_all(b" ").unwrap();
-            writer
-                .write_all(file.state.short_status_string().as_bytes())
-                .unwrap();
+            writer.write_all(code.as_bytes()).unwrap();
             writer.write_all(b" ").unwrap();
             writer.reset().unwrap();
             writer.write_all(file.name.as_bytes()).unwrap();
@@ -502,8 +1310,9 @@ impl RepoStatus {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use git2::{BranchType, Commit, Repository, Signature, SubmoduleUpdateOptions, Time};
+    use git2::{BranchType, Commit, ResetType, Repository, Signature, SubmoduleUpdateOptions, Time};
     use indoc::indoc;
+    use sha1::{Digest, Sha1};
     use std::fs;
     use temp_testdir::TempDir;
     use termcolor::Buffer;
@@ -592,6 +1401,49 @@ mod tests {
         .unwrap();
     }
 
+    // Builds two commits that each change `file` differently off of HEAD, then merges them so
+    // the repo's on-disk index is left with real stage 1/2/3 conflict entries for `file`,
+    // restoring the working directory to HEAD's content first.
+    fn create_conflicted_merge_index(repo: &Repository, file: &Path) {
+        let base_commit = repo.head().unwrap().peel_to_commit().unwrap();
+        let signature = Signature::new("Tucan", "me@me.com", &Time::new(20, 0)).unwrap();
+
+        write_to_file(repo, file, "our change");
+        stage_file(repo, file);
+        let our_tree_oid = repo.index().unwrap().write_tree().unwrap();
+        let our_tree = repo.find_tree(our_tree_oid).unwrap();
+        let our_commit_oid = repo
+            .commit(None, &signature, &signature, "ours", &our_tree, &[&base_commit])
+            .unwrap();
+        let our_commit = repo.find_commit(our_commit_oid).unwrap();
+
+        repo.reset(base_commit.as_object(), ResetType::Hard, None)
+            .unwrap();
+
+        write_to_file(repo, file, "their change");
+        stage_file(repo, file);
+        let their_tree_oid = repo.index().unwrap().write_tree().unwrap();
+        let their_tree = repo.find_tree(their_tree_oid).unwrap();
+        let their_commit_oid = repo
+            .commit(
+                None,
+                &signature,
+                &signature,
+                "theirs",
+                &their_tree,
+                &[&base_commit],
+            )
+            .unwrap();
+        let their_commit = repo.find_commit(their_commit_oid).unwrap();
+
+        repo.reset(base_commit.as_object(), ResetType::Hard, None)
+            .unwrap();
+
+        let mut merged_index = repo.merge_commits(&our_commit, &their_commit, None).unwrap();
+        repo.set_index(&mut merged_index).unwrap();
+        merged_index.write().unwrap();
+    }
+
     fn add_submodule(path: &Path, submodule_url: &str, submodule_path: &str) -> () {
         let repo = Repository::init(path).unwrap();
         let mut submodule = repo
@@ -1063,17 +1915,47 @@ mod tests {
     }
 
     #[test]
-    fn test_no_untracked_file() {
+    fn test_file_renamed_in_index_is_reported_as_a_single_rename_entry() {
         let file_names = vec!["one", "two", "three", "four"];
         let files = file_names.iter().map(|n| Path::new(n)).collect();
         let temp_dir = TempDir::default();
         let repo = test_repo(temp_dir.to_str().unwrap(), &files);
 
-        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+        fs::rename(
+            repo.workdir().unwrap().join("one"),
+            repo.workdir().unwrap().join("renamed_one"),
+        )
+        .unwrap();
+        let mut index = repo.index().unwrap();
+        index.remove_path(Path::new("one")).unwrap();
+        index.add_path(Path::new("renamed_one")).unwrap();
+        index.write().unwrap();
 
-        let mut writer = Buffer::no_color();
-        assert_eq!(status.write_untracked_message(&mut writer), false);
-    }
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+
+        let expected = indoc! {"\
+            Changes to be committed:
+              (use \"git restore --staged <file>...\" to unstage)
+                    renamed:    one -> renamed_one
+
+            "};
+        let mut writer = Buffer::no_color();
+        assert_eq!(status.write_staged_message(&mut writer), true);
+        assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_no_untracked_file() {
+        let file_names = vec!["one", "two", "three", "four"];
+        let files = file_names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+
+        let mut writer = Buffer::no_color();
+        assert_eq!(status.write_untracked_message(&mut writer), false);
+    }
 
     #[test]
     fn test_untracked_file() {
@@ -1127,11 +2009,57 @@ mod tests {
         assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), expected);
     }
 
+    #[test]
+    fn test_untracked_mode_all_lists_files_inside_untracked_directories() {
+        let file_names = vec!["one", "two", "three", "four"];
+        let files = file_names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+
+        write_to_file(&repo, Path::new("b/path/to/a/file"), "stuff");
+        write_to_file(&repo, Path::new("a_new_file"), "stuff");
+
+        let status =
+            RepoStatus::new_with_untracked_mode(repo.workdir().unwrap(), Some(UntrackedMode::All))
+                .unwrap();
+
+        let expected = indoc! {"\
+            Untracked files:
+              (use \"git add <file>...\" to include in what will be committed)
+                    a_new_file
+                    b/path/to/a/file
+
+            "};
+
+        let mut writer = Buffer::no_color();
+        assert_eq!(status.write_untracked_message(&mut writer), true);
+        assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_untracked_mode_no_hides_untracked_section() {
+        let file_names = vec!["one", "two", "three", "four"];
+        let files = file_names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+
+        write_to_file(&repo, Path::new("b/path/to/a/file"), "stuff");
+        write_to_file(&repo, Path::new("a_new_file"), "stuff");
+
+        let status =
+            RepoStatus::new_with_untracked_mode(repo.workdir().unwrap(), Some(UntrackedMode::No))
+                .unwrap();
+
+        let mut writer = Buffer::no_color();
+        assert_eq!(status.write_untracked_message(&mut writer), false);
+        assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), "");
+    }
+
     #[test]
     fn test_no_change_epilog() {
         let expected = "nothing to commit, working tree clean\n".to_string();
         let mut writer = Buffer::no_color();
-        RepoStatus::write_epilog(&mut writer, false, false, false);
+        RepoStatus::write_epilog(&mut writer, false, false, false, false);
         assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), expected);
     }
 
@@ -1140,14 +2068,14 @@ mod tests {
         let expected =
             "no changes added to commit (use \"git add\" and/or \"git commit -a\")\n".to_string();
         let mut writer = Buffer::no_color();
-        RepoStatus::write_epilog(&mut writer, false, true, false);
+        RepoStatus::write_epilog(&mut writer, false, true, false, false);
         assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), expected);
     }
 
     #[test]
     fn test_staged_epilog() {
         let mut writer = Buffer::no_color();
-        RepoStatus::write_epilog(&mut writer, true, false, false);
+        RepoStatus::write_epilog(&mut writer, true, false, false, false);
         assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), "");
     }
 
@@ -1158,7 +2086,7 @@ mod tests {
                 .to_string();
 
         let mut writer = Buffer::no_color();
-        RepoStatus::write_epilog(&mut writer, false, false, true);
+        RepoStatus::write_epilog(&mut writer, false, false, true, false);
         assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), expected);
     }
 
@@ -1167,17 +2095,27 @@ mod tests {
         let expected =
             "no changes added to commit (use \"git add\" and/or \"git commit -a\")\n".to_string();
         let mut writer = Buffer::no_color();
-        RepoStatus::write_epilog(&mut writer, false, true, true);
+        RepoStatus::write_epilog(&mut writer, false, true, true, false);
         assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), expected);
     }
 
     #[test]
     fn test_staged_overrides_unstaged_epilog() {
         let mut writer = Buffer::no_color();
-        RepoStatus::write_epilog(&mut writer, true, true, false);
+        RepoStatus::write_epilog(&mut writer, true, true, false, false);
         assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), "");
     }
 
+    #[test]
+    fn test_operation_in_progress_epilog() {
+        let expected =
+            "nothing to commit but conflicts present; fix conflicts and run \"git commit\"\n"
+                .to_string();
+        let mut writer = Buffer::no_color();
+        RepoStatus::write_epilog(&mut writer, false, false, false, true);
+        assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), expected);
+    }
+
     #[test]
     fn test_default_untracked_color() {
         let file_names = vec!["one", "two", "three", "four"];
@@ -1324,7 +2262,7 @@ mod tests {
             A  a_new_file
             "};
         let mut writer = Buffer::no_color();
-        status.write_short_staged(&mut writer);
+        status.write_short_staged_and_unstaged(&mut writer);
         assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), expected);
     }
 
@@ -1342,10 +2280,147 @@ mod tests {
 
         let expected = " M four\n M one/nested/a/bit.txt\n";
         let mut writer = Buffer::no_color();
-        status.write_short_unstaged(&mut writer);
+        status.write_short_staged_and_unstaged(&mut writer);
+        assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), expected);
+    }
+
+    #[test]
+    fn short_message_pathspec_restricts_to_matching_directory() {
+        let file_names = vec!["one/nested/a/bit.txt", "two", "three", "four"];
+        let files = file_names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+
+        write_to_file(&repo, files[0], "what???");
+        write_to_file(&repo, files[3], "what???");
+
+        let status =
+            RepoStatus::new_with_pathspecs(repo.workdir().unwrap(), &["one/"]).unwrap();
+
+        let expected = " M one/nested/a/bit.txt\n";
+        let mut writer = Buffer::no_color();
+        status.write_short_staged_and_unstaged(&mut writer);
+        assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), expected);
+    }
+
+    #[test]
+    fn short_message_staged_and_unstaged_changes_to_the_same_path_combine_onto_one_line() {
+        let file_names = vec!["one"];
+        let files = file_names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+
+        write_to_file(&repo, Path::new("one"), "staged changes");
+        stage_file(&repo, Path::new("one"));
+        write_to_file(&repo, Path::new("one"), "changed again, unstaged");
+
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+
+        let expected = "MM one\n";
+        let mut writer = Buffer::no_color();
+        status.write_short_staged_and_unstaged(&mut writer);
         assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), expected);
     }
 
+    #[test]
+    fn test_staged_statuses_prefix_matches_the_naive_walk() {
+        let file_names = vec!["one/nested/a/bit.txt", "two", "three", "four"];
+        let files = file_names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+
+        write_to_file(&repo, files[0], "staged changes");
+        stage_file(&repo, files[0]);
+        write_to_file(&repo, Path::new("new_file"), "new content");
+        stage_file(&repo, Path::new("new_file"));
+        write_to_file(&repo, files[3], "unstaged, should not show up as staged");
+
+        let workdir = repo.workdir().unwrap();
+        let naive: Vec<String> = RepoStatus::new(workdir)
+            .unwrap()
+            .index_diff
+            .entries
+            .iter()
+            .map(|entry| format!("{}  {}", entry.state.short_status_string(), entry.name))
+            .collect();
+        let mut naive_sorted = naive;
+        naive_sorted.sort();
+
+        let mut pruned_sorted = RepoStatus::staged_statuses(workdir, None, None);
+        pruned_sorted.sort();
+        assert_eq!(pruned_sorted, naive_sorted);
+
+        let scoped = RepoStatus::staged_statuses(workdir, Some("one/"), None);
+        assert_eq!(scoped, vec!["M  one/nested/a/bit.txt".to_string()]);
+    }
+
+    // Appends a minimal "TREE" extension record for `directory` to the on-disk index at
+    // `index_path`, caching `oid` for it, and rewrites the trailing checksum to match - standing
+    // in for the cache git itself maintains as entries are added/written, which `Repository::clone`
+    // doesn't populate on its own.
+    fn write_cache_tree_extension(index_path: &Path, directory: &str, oid: &[u8]) {
+        let mut contents = fs::read(index_path).unwrap();
+        let checksum_start = contents.len() - 20;
+
+        let mut body = vec![];
+        body.extend_from_slice(directory.as_bytes());
+        body.push(0);
+        body.extend_from_slice(b"0 0\n");
+        body.extend_from_slice(oid);
+
+        let mut extension = b"TREE".to_vec();
+        extension.extend_from_slice(&(body.len() as u32).to_be_bytes());
+        extension.extend_from_slice(&body);
+        contents.splice(checksum_start..checksum_start, extension);
+
+        let new_len = contents.len();
+        let checksum = Sha1::digest(&contents[..new_len - 20]);
+        contents[new_len - 20..].copy_from_slice(&checksum);
+        fs::write(index_path, contents).unwrap();
+    }
+
+    #[test]
+    fn test_staged_subtree_unchanged_is_false_without_a_cached_tree_oid() {
+        let files = vec![Path::new("one/two.txt")];
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+
+        assert!(!RepoStatus::staged_subtree_unchanged(&repo, None));
+    }
+
+    #[test]
+    fn test_staged_subtree_unchanged_is_true_when_the_cached_root_oid_matches_head() {
+        let files = vec![Path::new("one/two.txt"), Path::new("three.txt")];
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+        let head_tree_oid = repo.head().unwrap().peel_to_tree().unwrap().id();
+        write_cache_tree_extension(&repo.path().join("index"), "", head_tree_oid.as_bytes());
+
+        assert!(RepoStatus::staged_subtree_unchanged(&repo, None));
+    }
+
+    #[test]
+    fn test_staged_subtree_unchanged_is_true_for_an_unchanged_nested_directory() {
+        let files = vec![Path::new("one/two.txt"), Path::new("three.txt")];
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+        let head_tree = repo.head().unwrap().peel_to_tree().unwrap();
+        let subtree_oid = head_tree.get_path(Path::new("one")).unwrap().id();
+        write_cache_tree_extension(&repo.path().join("index"), "one", subtree_oid.as_bytes());
+
+        assert!(RepoStatus::staged_subtree_unchanged(&repo, Some("one/")));
+    }
+
+    #[test]
+    fn test_staged_subtree_unchanged_is_false_when_the_cached_oid_is_stale() {
+        let files = vec![Path::new("one/two.txt")];
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+        write_cache_tree_extension(&repo.path().join("index"), "", &[0u8; 20]);
+
+        assert!(!RepoStatus::staged_subtree_unchanged(&repo, None));
+    }
+
     #[test]
     fn short_untracked_file() {
         let file_names = vec!["one", "two", "three", "four"];
@@ -1365,6 +2440,126 @@ mod tests {
         assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), expected);
     }
 
+    #[test]
+    fn short_untracked_directory_of_only_new_files_by_mode() {
+        let file_names = vec!["one", "two", "three", "four"];
+        let files = file_names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+
+        write_to_file(&repo, Path::new("untracked_dir/a"), "stuff");
+        write_to_file(&repo, Path::new("untracked_dir/b"), "stuff");
+
+        // `normal` (git's own default) collapses the directory to one entry.
+        let normal = RepoStatus::new_with_untracked_mode(
+            repo.workdir().unwrap(),
+            Some(UntrackedMode::Normal),
+        )
+        .unwrap();
+        let mut writer = Buffer::no_color();
+        normal.write_short_untracked(&mut writer);
+        assert_eq!(
+            String::from_utf8(writer.into_inner()).unwrap(),
+            "?? untracked_dir/\n"
+        );
+
+        // `all` lists every file inside it individually.
+        let all =
+            RepoStatus::new_with_untracked_mode(repo.workdir().unwrap(), Some(UntrackedMode::All))
+                .unwrap();
+        let mut writer = Buffer::no_color();
+        all.write_short_untracked(&mut writer);
+        assert_eq!(
+            String::from_utf8(writer.into_inner()).unwrap(),
+            "?? untracked_dir/a\n?? untracked_dir/b\n"
+        );
+
+        // `no` omits the `??` section entirely.
+        let no =
+            RepoStatus::new_with_untracked_mode(repo.workdir().unwrap(), Some(UntrackedMode::No))
+                .unwrap();
+        let mut writer = Buffer::no_color();
+        no.write_short_untracked(&mut writer);
+        assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), "");
+    }
+
+    #[test]
+    fn test_short_branch_header_ahead_of_upstream() {
+        let file_names = vec!["one", "two"];
+        let files = file_names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+
+        repo.set_head("refs/heads/tip").unwrap();
+        let mut branch = repo.find_branch("tip", BranchType::Local).unwrap();
+        branch.set_upstream(Some("origin/half")).unwrap();
+
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+        let mut writer = Buffer::no_color();
+        status.write_short_branch_header(&mut writer).unwrap();
+        assert_eq!(
+            String::from_utf8(writer.into_inner()).unwrap(),
+            "## tip...origin/half [ahead 1]\n"
+        );
+    }
+
+    #[test]
+    fn test_short_branch_header_no_upstream() {
+        let file_names = vec!["one", "two"];
+        let files = file_names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+        let mut branch = repo.find_branch("tip", BranchType::Local).unwrap();
+        branch.set_upstream(None).unwrap();
+
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+        let mut writer = Buffer::no_color();
+        status.write_short_branch_header(&mut writer).unwrap();
+        assert_eq!(
+            String::from_utf8(writer.into_inner()).unwrap(),
+            "## tip\n"
+        );
+    }
+
+    #[test]
+    fn test_short_branch_header_detached() {
+        let file_names = vec!["one", "two"];
+        let files = file_names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+        let oid = repo.head().unwrap().peel_to_commit().unwrap().id();
+        repo.set_head_detached(oid).unwrap();
+
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+        let mut writer = Buffer::no_color();
+        status.write_short_branch_header(&mut writer).unwrap();
+        assert_eq!(
+            String::from_utf8(writer.into_inner()).unwrap(),
+            "## HEAD (no branch)\n"
+        );
+    }
+
+    #[test]
+    fn test_short_message_with_branch_prepends_header() {
+        let file_names = vec!["one", "two", "three", "four"];
+        let files = file_names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+        let mut branch = repo.find_branch("tip", BranchType::Local).unwrap();
+        branch.set_upstream(None).unwrap();
+
+        write_to_file(&repo, Path::new("some_new_file"), "stuff");
+
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+        let mut writer = Buffer::no_color();
+        status.write_short_message(&mut writer, true).unwrap();
+        let expected = indoc! {"\
+            ## tip
+            ?? some_new_file
+            "};
+        assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), expected);
+    }
+
     #[test]
     fn test_upstream_branch_tip_gone() {
         let file_names = vec!["one", "two", "three", "four"];
@@ -1385,4 +2580,633 @@ mod tests {
             "};
         assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), expected);
     }
+
+    #[test]
+    fn test_porcelain_v2_branch_headers_ahead_of_upstream() {
+        let file_names = vec!["one", "two"];
+        let files = file_names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+
+        repo.set_head("refs/heads/tip").unwrap();
+        let mut branch = repo.find_branch("tip", BranchType::Local).unwrap();
+        branch.set_upstream(Some("origin/half")).unwrap();
+        let oid = repo.head().unwrap().peel_to_commit().unwrap().id();
+
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+        let mut writer = Buffer::no_color();
+        status.write_porcelain_v2_branch_headers(&mut writer, b"\n").unwrap();
+        let expected = format! {"\
+            # branch.oid {oid}
+            # branch.head tip
+            # branch.upstream origin/half
+            # branch.ab +1 -0
+            ", oid=oid};
+        assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_porcelain_v2_branch_headers_no_upstream() {
+        let file_names = vec!["one", "two"];
+        let files = file_names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+        let mut branch = repo.find_branch("tip", BranchType::Local).unwrap();
+        branch.set_upstream(None).unwrap();
+        let oid = repo.head().unwrap().peel_to_commit().unwrap().id();
+
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+        let mut writer = Buffer::no_color();
+        status.write_porcelain_v2_branch_headers(&mut writer, b"\n").unwrap();
+        let expected = format! {"\
+            # branch.oid {oid}
+            # branch.head tip
+            ", oid=oid};
+        assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_porcelain_v2_branch_headers_detached() {
+        let file_names = vec!["one", "two"];
+        let files = file_names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+        let oid = repo.head().unwrap().peel_to_commit().unwrap().id();
+        repo.set_head_detached(oid).unwrap();
+
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+        let mut writer = Buffer::no_color();
+        status.write_porcelain_v2_branch_headers(&mut writer, b"\n").unwrap();
+        let expected = format! {"\
+            # branch.oid {oid}
+            # branch.head (detached)
+            ", oid=oid};
+        assert_eq!(String::from_utf8(writer.into_inner()).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_porcelain_v2_message_has_no_color_and_lists_staged_unstaged_and_untracked() {
+        let file_names = vec!["one", "two", "three", "four"];
+        let files = file_names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+
+        let new_file = Path::new("a_new_file");
+        write_to_file(&repo, new_file, "stuff");
+        stage_file(&repo, new_file);
+        write_to_file(&repo, Path::new("one"), "what???");
+        write_to_file(&repo, Path::new("untracked"), "stuff");
+
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+        let mut writer = Buffer::ansi();
+        status.write_porcelain_v2_message(&mut writer, false).unwrap();
+        let output = String::from_utf8(writer.into_inner()).unwrap();
+
+        assert!(!output.contains('\x1b'));
+        assert!(output.contains(
+            "1 A. N... 100644 100644 100644 \
+             0000000000000000000000000000000000000000 \
+             0000000000000000000000000000000000000000 a_new_file\n"
+        ));
+        assert!(output.contains(
+            "1 .M N... 100644 100644 100644 \
+             0000000000000000000000000000000000000000 \
+             0000000000000000000000000000000000000000 one\n"
+        ));
+        assert!(output.contains("? untracked\n"));
+    }
+
+    #[test]
+    fn test_porcelain_v2_message_nul_terminated_uses_nul_instead_of_newline() {
+        let file_names = vec!["one", "two"];
+        let files = file_names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+
+        write_to_file(&repo, Path::new("one"), "what???");
+        write_to_file(&repo, Path::new("untracked"), "stuff");
+
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+        let mut writer = Buffer::no_color();
+        status.write_porcelain_v2_message(&mut writer, true).unwrap();
+        let output = String::from_utf8(writer.into_inner()).unwrap();
+
+        assert!(!output.contains('\n'));
+        assert!(output.contains(
+            "1 .M N... 100644 100644 100644 \
+             0000000000000000000000000000000000000000 \
+             0000000000000000000000000000000000000000 one\0"
+        ));
+        assert!(output.contains("? untracked\0"));
+    }
+
+    #[test]
+    fn test_porcelain_v2_message_emits_rename_entry() {
+        let file_names = vec!["one", "two", "three", "four"];
+        let files = file_names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+
+        fs::rename(
+            repo.workdir().unwrap().join("one"),
+            repo.workdir().unwrap().join("renamed_one"),
+        )
+        .unwrap();
+        let mut index = repo.index().unwrap();
+        index.remove_path(Path::new("one")).unwrap();
+        index.add_path(Path::new("renamed_one")).unwrap();
+        index.write().unwrap();
+
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+        let mut writer = Buffer::no_color();
+        status.write_porcelain_v2_message(&mut writer, false).unwrap();
+        let output = String::from_utf8(writer.into_inner()).unwrap();
+
+        assert!(output.contains(
+            "2 R. N... 100644 100644 100644 \
+             0000000000000000000000000000000000000000 \
+             0000000000000000000000000000000000000000 R100 renamed_one\tone\n"
+        ));
+    }
+
+    #[test]
+    fn test_porcelain_v2_message_combines_a_staged_and_unstaged_change_to_the_same_path() {
+        let file_names = vec!["one"];
+        let files = file_names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+
+        write_to_file(&repo, Path::new("one"), "staged changes");
+        stage_file(&repo, Path::new("one"));
+        write_to_file(&repo, Path::new("one"), "changed again, unstaged");
+
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+        let mut writer = Buffer::no_color();
+        status.write_porcelain_v2_message(&mut writer, false).unwrap();
+        let output = String::from_utf8(writer.into_inner()).unwrap();
+
+        assert!(output.contains(
+            "1 MM N... 100644 100644 100644 \
+             0000000000000000000000000000000000000000 \
+             0000000000000000000000000000000000000000 one\n"
+        ));
+        assert!(!output.contains("1 M. "));
+        assert!(!output.contains("1 .M "));
+    }
+
+    #[test]
+    fn test_porcelain_v2_submodule_field_reports_which_aspects_changed() {
+        assert_eq!(
+            RepoStatus::porcelain_v2_submodule_field(&Status::Modified(Some(
+                "new commits".to_string()
+            ))),
+            "SC.."
+        );
+        assert_eq!(
+            RepoStatus::porcelain_v2_submodule_field(&Status::Modified(Some(
+                "modified content, untracked content".to_string()
+            ))),
+            "S.MU"
+        );
+        assert_eq!(
+            RepoStatus::porcelain_v2_submodule_field(&Status::Modified(None)),
+            "N..."
+        );
+        assert_eq!(
+            RepoStatus::porcelain_v2_submodule_field(&Status::New),
+            "N..."
+        );
+    }
+
+    #[test]
+    fn test_long_message_renders_cherry_pick_in_progress_instead_of_erroring() {
+        let file_names = vec!["one", "two"];
+        let files = file_names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+        let commit = repo.head().unwrap().peel_to_commit().unwrap();
+        fs::write(repo.path().join("CHERRY_PICK_HEAD"), commit.id().to_string()).unwrap();
+
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+        let mut writer = Buffer::no_color();
+        status.write_long_message(&mut writer).unwrap();
+        let output = String::from_utf8(writer.into_inner()).unwrap();
+
+        assert!(output.contains(&format! {
+            "You are currently cherry-picking commit {}.\n\n",
+            commit.id()
+        }));
+        assert!(output.contains(
+            "nothing to commit but conflicts present; fix conflicts and run \"git commit\"\n"
+        ));
+    }
+
+    #[test]
+    fn test_long_message_renders_revert_in_progress_instead_of_erroring() {
+        let file_names = vec!["one", "two"];
+        let files = file_names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+        let commit = repo.head().unwrap().peel_to_commit().unwrap();
+        fs::write(repo.path().join("REVERT_HEAD"), commit.id().to_string()).unwrap();
+
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+        let mut writer = Buffer::no_color();
+        status.write_long_message(&mut writer).unwrap();
+        let output = String::from_utf8(writer.into_inner()).unwrap();
+
+        assert!(output.contains(&format! {
+            "You are currently reverting commit {}.\n\n",
+            commit.id()
+        }));
+    }
+
+    #[test]
+    fn test_long_message_renders_interactive_rebase_in_progress_instead_of_erroring() {
+        let file_names = vec!["one", "two"];
+        let files = file_names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+        let commit = repo.head().unwrap().peel_to_commit().unwrap();
+
+        let rebase_merge_dir = repo.path().join("rebase-merge");
+        fs::create_dir_all(&rebase_merge_dir).unwrap();
+        fs::write(rebase_merge_dir.join("onto"), commit.id().to_string()).unwrap();
+        fs::write(rebase_merge_dir.join("msgnum"), "2").unwrap();
+        fs::write(rebase_merge_dir.join("end"), "5").unwrap();
+
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+        let mut writer = Buffer::no_color();
+        status.write_long_message(&mut writer).unwrap();
+        let output = String::from_utf8(writer.into_inner()).unwrap();
+
+        assert!(output.contains(&format! {
+            "interactive rebase in progress; onto {}\nLast command done (2/5):\n\n",
+            commit.id()
+        }));
+    }
+
+    #[test]
+    fn test_long_message_renders_bisect_in_progress_instead_of_erroring() {
+        let file_names = vec!["one", "two"];
+        let files = file_names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+        fs::write(repo.path().join("BISECT_LOG"), "").unwrap();
+        fs::write(repo.path().join("BISECT_START"), "master").unwrap();
+
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+        let mut writer = Buffer::no_color();
+        status.write_long_message(&mut writer).unwrap();
+        let output = String::from_utf8(writer.into_inner()).unwrap();
+
+        assert!(output.contains("You are currently bisecting, started from branch 'master'.\n\n"));
+    }
+
+    #[test]
+    fn test_conflicted_path_is_reported_as_both_modified() {
+        let file_names = vec!["one", "two"];
+        let files = file_names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+        create_conflicted_merge_index(&repo, Path::new("one"));
+
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+        assert_eq!(
+            status.unmerged,
+            vec![StatusEntry {
+                name: "one".to_string(),
+                state: Status::Conflict(ConflictKind::BothModified),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_short_message_reports_conflict_with_its_two_letter_code() {
+        let file_names = vec!["one", "two"];
+        let files = file_names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+        create_conflicted_merge_index(&repo, Path::new("one"));
+
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+        let mut writer = Buffer::no_color();
+        status.write_short_unmerged(&mut writer);
+        assert_eq!(
+            String::from_utf8(writer.into_inner()).unwrap(),
+            "UU one\n"
+        );
+    }
+
+    #[test]
+    fn test_long_message_lists_unmerged_paths_and_replaces_nothing_to_commit() {
+        let file_names = vec!["one", "two"];
+        let files = file_names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+        create_conflicted_merge_index(&repo, Path::new("one"));
+
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+        let mut writer = Buffer::no_color();
+        status.write_long_message(&mut writer).unwrap();
+        let output = String::from_utf8(writer.into_inner()).unwrap();
+
+        assert!(output.contains("Unmerged paths:"));
+        assert!(output.contains("  (use \"git add <file>...\" to mark resolution)"));
+        assert!(output.contains("both modified:   one"));
+        assert!(output.contains(
+            "nothing to commit but conflicts present; fix conflicts and run \"git commit\"\n"
+        ));
+    }
+
+    #[test]
+    fn test_porcelain_v2_message_emits_unmerged_entry() {
+        let file_names = vec!["one", "two"];
+        let files = file_names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+        create_conflicted_merge_index(&repo, Path::new("one"));
+
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+        let mut writer = Buffer::no_color();
+        status.write_porcelain_v2_message(&mut writer, false).unwrap();
+        let output = String::from_utf8(writer.into_inner()).unwrap();
+
+        assert!(output.contains(
+            "u UU N... 100644 100644 100644 100644 \
+             0000000000000000000000000000000000000000 \
+             0000000000000000000000000000000000000000 \
+             0000000000000000000000000000000000000000 one\n"
+        ));
+    }
+
+    #[test]
+    fn test_conflict_kind_from_stages() {
+        assert_eq!(
+            ConflictKind::from_stages(&[1, 2, 3]),
+            Some(ConflictKind::BothModified)
+        );
+        assert_eq!(
+            ConflictKind::from_stages(&[2, 3]),
+            Some(ConflictKind::BothAdded)
+        );
+        assert_eq!(
+            ConflictKind::from_stages(&[1, 2]),
+            Some(ConflictKind::DeletedByThem)
+        );
+        assert_eq!(
+            ConflictKind::from_stages(&[1, 3]),
+            Some(ConflictKind::DeletedByUs)
+        );
+        assert_eq!(
+            ConflictKind::from_stages(&[2]),
+            Some(ConflictKind::AddedByUs)
+        );
+        assert_eq!(
+            ConflictKind::from_stages(&[3]),
+            Some(ConflictKind::AddedByThem)
+        );
+        assert_eq!(
+            ConflictKind::from_stages(&[1]),
+            Some(ConflictKind::BothDeleted)
+        );
+        assert_eq!(ConflictKind::from_stages(&[]), None);
+    }
+
+    #[test]
+    fn test_prompt_summary_clean_branch() {
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &vec![Path::new("why_not")]);
+        repo.set_head("refs/heads/tip").unwrap();
+
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+        assert_eq!(status.prompt_summary().unwrap(), "tip");
+    }
+
+    #[test]
+    fn test_prompt_summary_detached_head() {
+        let file_names = vec!["one", "two", "three", "four"];
+        let files = file_names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.set_head_detached(head.id()).unwrap();
+
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+        assert_eq!(status.prompt_summary().unwrap(), "82578fa");
+    }
+
+    #[test]
+    fn test_prompt_summary_ahead_and_behind_remote() {
+        let file_names = vec!["one", "two", "three", "four"];
+        let files = file_names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+
+        repo.set_head("refs/heads/half").unwrap();
+        let mut branch = repo.find_branch("half", BranchType::Local).unwrap();
+        branch.set_upstream(Some("origin/tip")).unwrap();
+
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+        assert_eq!(status.prompt_summary().unwrap(), "half\u{21E3}2");
+    }
+
+    #[test]
+    fn test_prompt_summary_dirty_flags() {
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &vec![Path::new("one")]);
+        repo.set_head("refs/heads/tip").unwrap();
+
+        commit_file(&repo, Path::new("two"));
+        write_to_file(&repo, Path::new("one"), "staged changes");
+        stage_file(&repo, Path::new("one"));
+        write_to_file(&repo, Path::new("two"), "unstaged changes");
+        write_to_file(&repo, Path::new("new_file"), "untracked");
+
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+        assert_eq!(status.prompt_summary().unwrap(), "tip\u{21E1}1+1!1?1");
+    }
+
+    #[test]
+    fn test_prompt_summary_diverged_from_upstream() {
+        let file_names = vec!["one", "two", "three", "four"];
+        let files = file_names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+
+        repo.set_head("refs/heads/half").unwrap();
+        let mut branch = repo.find_branch("half", BranchType::Local).unwrap();
+        branch.set_upstream(Some("origin/tip")).unwrap();
+        commit_file(&repo, Path::new("five"));
+
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+        assert_eq!(status.prompt_summary().unwrap(), "half\u{21D5}");
+    }
+
+    #[test]
+    fn test_prompt_summary_conflicted_paths() {
+        let file_names = vec!["one", "two"];
+        let files = file_names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+        create_conflicted_merge_index(&repo, Path::new("one"));
+
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+        assert_eq!(status.prompt_summary().unwrap(), "tip\u{2716}1");
+    }
+
+    #[test]
+    fn test_prompt_summary_stash_marker() {
+        let file_names = vec!["one", "two"];
+        let files = file_names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let mut repo = test_repo(temp_dir.to_str().unwrap(), &files);
+
+        write_to_file(&repo, Path::new("one"), "stashed changes");
+        let signature = Signature::new("Tucan", "me@me.com", &Time::new(20, 0)).unwrap();
+        repo.stash_save(&signature, "a stash", None).unwrap();
+
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+        assert_eq!(status.prompt_summary().unwrap(), "tip$1");
+    }
+
+    #[test]
+    fn test_default_stash_color() {
+        let file_names = vec!["one", "two", "three", "four"];
+        let files = file_names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+        let color = status.get_color(StatusColorSlot::Stash);
+
+        assert_eq!(color, Color::Blue);
+    }
+
+    #[test]
+    fn test_overridden_stash_color() {
+        let file_names = vec!["one", "two", "three", "four"];
+        let files = file_names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+
+        let mut config = repo.config().unwrap();
+        config.set_str("color.status.stash", "cyan").unwrap();
+
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+        let color = status.get_color(StatusColorSlot::Stash);
+
+        assert_eq!(color, Color::Cyan);
+    }
+
+    #[test]
+    fn test_report_clean_branch() {
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &vec![Path::new("why_not")]);
+        repo.set_head("refs/heads/tip").unwrap();
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+        let report = status.report().unwrap();
+        assert_eq!(report.head, head.id().to_string());
+        assert_eq!(report.branch, Some("tip".to_string()));
+        assert_eq!(report.upstream, Some("origin/tip".to_string()));
+        assert_eq!(report.ahead, 0);
+        assert_eq!(report.behind, 0);
+        assert_eq!(report.operation, None);
+        assert!(report.staged.is_empty());
+        assert!(report.unstaged.is_empty());
+        assert!(report.untracked.is_empty());
+        assert!(report.unmerged.is_empty());
+    }
+
+    #[test]
+    fn test_report_detached_head() {
+        let file_names = vec!["one", "two", "three", "four"];
+        let files = file_names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+        let head = repo.head().unwrap().peel_to_commit().unwrap();
+        repo.set_head_detached(head.id()).unwrap();
+
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+        let report = status.report().unwrap();
+        assert_eq!(report.head, head.id().to_string());
+        assert_eq!(report.branch, None);
+        assert_eq!(report.upstream, None);
+    }
+
+    #[test]
+    fn test_report_behind_remote_branch() {
+        let file_names = vec!["one", "two", "three", "four"];
+        let files = file_names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &files);
+
+        repo.set_head("refs/heads/half").unwrap();
+        let mut branch = repo.find_branch("half", BranchType::Local).unwrap();
+        branch.set_upstream(Some("origin/tip")).unwrap();
+
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+        let report = status.report().unwrap();
+        assert_eq!(report.upstream, Some("origin/tip".to_string()));
+        assert_eq!(report.ahead, 0);
+        assert_eq!(report.behind, 2);
+    }
+
+    #[test]
+    fn test_report_staged_unstaged_and_untracked() {
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &vec![Path::new("one")]);
+        repo.set_head("refs/heads/tip").unwrap();
+
+        commit_file(&repo, Path::new("two"));
+        write_to_file(&repo, Path::new("one"), "staged changes");
+        stage_file(&repo, Path::new("one"));
+        write_to_file(&repo, Path::new("two"), "unstaged changes");
+        write_to_file(&repo, Path::new("new_file"), "untracked");
+
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+        let report = status.report().unwrap();
+        assert_eq!(
+            report.staged,
+            vec![StatusEntry {
+                name: "one".to_string(),
+                state: Status::Modified(None),
+            }]
+        );
+        assert_eq!(
+            report.unstaged,
+            vec![StatusEntry {
+                name: "two".to_string(),
+                state: Status::Modified(None),
+            }]
+        );
+        assert_eq!(
+            report.untracked,
+            vec![StatusEntry {
+                name: "new_file".to_string(),
+                state: Status::New,
+            }]
+        );
+        assert!(report.unmerged.is_empty());
+    }
+
+    #[test]
+    fn test_report_unmerged() {
+        let temp_dir = TempDir::default();
+        let repo = test_repo(temp_dir.to_str().unwrap(), &vec![Path::new("one")]);
+        create_conflicted_merge_index(&repo, Path::new("one"));
+
+        let status = RepoStatus::new(repo.workdir().unwrap()).unwrap();
+        let report = status.report().unwrap();
+        assert_eq!(
+            report.unmerged,
+            vec![StatusEntry {
+                name: "one".to_string(),
+                state: Status::Conflict(ConflictKind::BothModified),
+            }]
+        );
+        assert!(report.staged.is_empty());
+    }
 }
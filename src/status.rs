@@ -5,15 +5,28 @@
  *          https://www.boost.org/LICENSE_1_0.txt)
  */
 
+use serde::Serialize;
 use std::fmt;
 
 /// The status of a file in relation to the rest of the git repo.
-#[derive(PartialEq, Eq, Debug, Clone)]
+#[derive(PartialEq, Eq, Debug, Clone, Serialize)]
 pub enum Status {
     Current,
     New,
     Modified(Option<String>),
+    // The entry's git object type changed, e.g. a tracked file was replaced by a symlink, or
+    // vice versa, even though its size and mtime may not have.
+    TypeChange,
     Deleted,
+    // A deletion and an addition git paired up as the same content moving: the path it used to
+    // live at.  The new path is `StatusEntry::name`, as usual.
+    Renamed(String),
+    // Like `Renamed`, but the old path's content is still present too (e.g. `cp` instead of
+    // `mv`), so this isn't a delete on the old side.
+    Copied(String),
+    // A merge conflict: the index dropped the usual stage-0 entry for this path in favor of one
+    // entry per side that disagreed.  `ConflictKind` records which sides are actually present.
+    Conflict(ConflictKind),
 }
 impl Default for Status {
     fn default() -> Self {
@@ -26,7 +39,11 @@ impl fmt::Display for Status {
             Status::Current => fmt.write_str(""),
             Status::New => fmt.write_str("new file:   "),
             Status::Modified(_) => fmt.write_str("modified:   "),
+            Status::TypeChange => fmt.write_str("typechange: "),
             Status::Deleted => fmt.write_str("deleted:    "),
+            Status::Renamed(_) => fmt.write_str("renamed:    "),
+            Status::Copied(_) => fmt.write_str("copied:     "),
+            Status::Conflict(kind) => fmt.write_str(kind.label()),
         }
     }
 }
@@ -34,9 +51,85 @@ impl Status {
     pub fn is_modified(&self) -> bool {
         matches!(*self, Status::Modified(_))
     }
+
+    /// The single-letter code used in one column of git's `--short`/`--porcelain` status
+    /// format, e.g. `"M"` for a modified path.  Unmerged paths use `ConflictKind::code`'s own
+    /// two-letter pair instead of this, but it's provided here too for callers that only have a
+    /// `Status` in hand.
+    pub fn short_status_string(&self) -> &'static str {
+        match self {
+            Status::Current => " ",
+            Status::New => "A",
+            Status::Modified(_) => "M",
+            Status::TypeChange => "T",
+            Status::Deleted => "D",
+            Status::Renamed(_) => "R",
+            Status::Copied(_) => "C",
+            Status::Conflict(kind) => kind.code(),
+        }
+    }
+}
+
+/// Which sides of a merge conflict are present for a path, mirroring git's two-letter unmerged
+/// status codes (`UU`, `AA`, `DD`, `AU`, `UA`, `DU`, `UD`).
+#[derive(PartialEq, Eq, Debug, Clone, Copy, Serialize)]
+pub enum ConflictKind {
+    BothModified,
+    AddedByUs,
+    AddedByThem,
+    BothAdded,
+    DeletedByUs,
+    DeletedByThem,
+    BothDeleted,
+}
+
+impl ConflictKind {
+    /// Determines which sides of a conflict exist for a path from the index stages (1 = base,
+    /// 2 = ours, 3 = theirs) still present for it.  Returns `None` for a stage combination that
+    /// isn't actually a conflict, e.g. only a stage-0 entry.
+    pub fn from_stages(stages: &[u8]) -> Option<ConflictKind> {
+        let base = stages.contains(&1);
+        let ours = stages.contains(&2);
+        let theirs = stages.contains(&3);
+        match (base, ours, theirs) {
+            (true, true, true) => Some(ConflictKind::BothModified),
+            (false, true, true) => Some(ConflictKind::BothAdded),
+            (true, true, false) => Some(ConflictKind::DeletedByThem),
+            (true, false, true) => Some(ConflictKind::DeletedByUs),
+            (false, true, false) => Some(ConflictKind::AddedByUs),
+            (false, false, true) => Some(ConflictKind::AddedByThem),
+            (true, false, false) => Some(ConflictKind::BothDeleted),
+            (false, false, false) => None,
+        }
+    }
+
+    /// The two-letter short/porcelain status code, e.g. `"UU"` for both-modified.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ConflictKind::BothModified => "UU",
+            ConflictKind::AddedByUs => "AU",
+            ConflictKind::AddedByThem => "UA",
+            ConflictKind::BothAdded => "AA",
+            ConflictKind::DeletedByUs => "DU",
+            ConflictKind::DeletedByThem => "UD",
+            ConflictKind::BothDeleted => "DD",
+        }
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            ConflictKind::BothModified => "both modified:   ",
+            ConflictKind::AddedByUs => "added by us:     ",
+            ConflictKind::AddedByThem => "added by them:   ",
+            ConflictKind::BothAdded => "both added:      ",
+            ConflictKind::DeletedByUs => "deleted by us:   ",
+            ConflictKind::DeletedByThem => "deleted by them: ",
+            ConflictKind::BothDeleted => "both deleted:    ",
+        }
+    }
 }
 
-#[derive(PartialEq, Eq, Debug, Default, Clone)]
+#[derive(PartialEq, Eq, Debug, Default, Clone, Serialize)]
 pub struct StatusEntry {
     pub name: String,
     pub state: Status,
@@ -45,7 +138,14 @@ pub struct StatusEntry {
 impl fmt::Display for StatusEntry {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         fmt.write_str(&self.state.to_string())?;
-        fmt.write_str(&self.name)?;
+        match &self.state {
+            Status::Renamed(old_name) | Status::Copied(old_name) => {
+                fmt.write_str(old_name)?;
+                fmt.write_str(" -> ")?;
+                fmt.write_str(&self.name)?;
+            }
+            _ => fmt.write_str(&self.name)?,
+        }
         Ok(())
     }
 }
@@ -11,20 +11,65 @@ use std::path::Path;
 
 /// A tree of a repo.
 ///
+/// Rename detection here only ever compares HEAD to the index (staged renames), via libgit2's
+/// own `renames_head_to_index`; it does not implement the bespoke OID-pairing/similarity-scoring
+/// algorithm a from-scratch unstaged (worktree-to-index) rename or copy detector would need, and
+/// `Status::Copied` is consequently never constructed anywhere in this crate. That's tracked as
+/// follow-up work, not an oversight - see the note on `diff_against_index_with_options`.
 #[derive(Debug, Default, PartialEq)]
 pub struct TreeDiff {
     pub entries: Vec<StatusEntry>,
 }
 
 impl TreeDiff {
+    // Mirrors git's own `-M50%` default: a deletion and an addition whose content is at least
+    // this similar are reported as a single rename instead of a delete/add pair. Overridable via
+    // `diff_against_index_with_options`'s `rename_threshold`, the same way git's `-M<n>` flag and
+    // `status.renames` config override it.
+    pub const DEFAULT_RENAME_THRESHOLD: u16 = 50;
+
     pub fn diff_against_index(path: &Path) -> TreeDiff {
-        let repo = Repository::open(path).unwrap();
-        TreeDiff::diff_against_index_with_repo(&repo)
+        TreeDiff::diff_against_index_with_options(path, None, None)
+    }
+
+    /// Like `diff_against_index`, but when `path_prefix` is given, restricts the diff to that
+    /// pathspec. The index's cached tree object ids are not consulted here; skipping an
+    /// unchanged subtree by its cached id is done by the caller (`RepoStatus::staged_statuses`).
+    pub fn diff_against_index_with_prefix(path: &Path, path_prefix: Option<&str>) -> TreeDiff {
+        TreeDiff::diff_against_index_with_options(path, path_prefix, None)
     }
 
     pub fn diff_against_index_with_repo(repo: &Repository) -> TreeDiff {
+        TreeDiff::diff_against_index_with_repo_and_options(repo, None, None)
+    }
+
+    /// Like `diff_against_index_with_prefix`, but `rename_threshold` (a similarity percentage,
+    /// `0..=100`) overrides `DEFAULT_RENAME_THRESHOLD` when given, mirroring git's `-M<n>` flag.
+    ///
+    /// TODO(follow-up, unstaged rename/copy detection): this only tunes the staged
+    /// (HEAD-to-index) detection libgit2 already does for us; a worktree-to-index detector still
+    /// needs to be written from scratch (see the `TreeDiff` struct doc).
+    pub fn diff_against_index_with_options(
+        path: &Path,
+        path_prefix: Option<&str>,
+        rename_threshold: Option<u16>,
+    ) -> TreeDiff {
+        let repo = Repository::open(path).unwrap();
+        TreeDiff::diff_against_index_with_repo_and_options(&repo, path_prefix, rename_threshold)
+    }
+
+    fn diff_against_index_with_repo_and_options(
+        repo: &Repository,
+        path_prefix: Option<&str>,
+        rename_threshold: Option<u16>,
+    ) -> TreeDiff {
         let mut options = StatusOptions::new();
         options.show(StatusShow::Index);
+        options.renames_head_to_index(true);
+        options.rename_threshold(rename_threshold.unwrap_or(TreeDiff::DEFAULT_RENAME_THRESHOLD));
+        if let Some(prefix) = path_prefix {
+            options.pathspec(prefix);
+        }
         let diff = repo.statuses(Option::from(&mut options)).unwrap();
         TreeDiff::convert_git2_to_treediff(&diff)
     }
@@ -32,7 +77,22 @@ impl TreeDiff {
     fn convert_git2_to_treediff(statuses: &Statuses) -> TreeDiff {
         let mut entries = vec![];
         for status in statuses.iter() {
-            let state = TreeDiff::git2_status_to_treediff_status(status.status());
+            // Conflicted paths are reported separately, via `RepoStatus`'s own reading of the
+            // index's unmerged stages, so they carry the detail (which sides disagree) this
+            // plain git2 status flag doesn't.
+            if status.status().contains(git2::Status::CONFLICTED) {
+                continue;
+            }
+            let state = if status.status().contains(git2::Status::INDEX_RENAMED) {
+                let old_name = status
+                    .head_to_index()
+                    .and_then(|delta| delta.old_file().path())
+                    .map(|path| path.to_string_lossy().to_string())
+                    .unwrap_or_default();
+                Status::Renamed(old_name)
+            } else {
+                TreeDiff::git2_status_to_treediff_status(status.status())
+            };
             entries.push(StatusEntry {
                 name: status.path().unwrap().to_string(),
                 state,
@@ -177,4 +237,32 @@ mod tests {
     fn test_unsupported_status_from_libgit2() {
         TreeDiff::git2_status_to_treediff_status(git2::Status::WT_NEW);
     }
+
+    #[test]
+    fn test_get_tree_diff_a_renamed_file() {
+        let names = vec!["one.baz", "what.foo", "a/nested/flie"];
+        let files = names.iter().map(|n| Path::new(n)).collect();
+        let temp_dir = TempDir::default();
+        let repo_path = temp_dir.to_str().unwrap();
+        test_repo(repo_path, &files);
+
+        let repo = Repository::open(repo_path).unwrap();
+        let root = repo.path().parent().unwrap();
+        fs::rename(root.join(names[1]), root.join("renamed.foo")).unwrap();
+        let mut index = repo.index().unwrap();
+        index.remove_path(Path::new(names[1])).unwrap();
+        index.add_path(Path::new("renamed.foo")).unwrap();
+        index.write().unwrap();
+
+        let diff = TreeDiff::diff_against_index(&temp_dir);
+        assert_eq!(
+            diff,
+            TreeDiff {
+                entries: vec![StatusEntry {
+                    name: "renamed.foo".to_string(),
+                    state: Status::Renamed(names[1].to_string())
+                }]
+            }
+        );
+    }
 }
@@ -0,0 +1,313 @@
+/*
+ *          Copyright Nick G. 2021.
+ * Distributed under the Boost Software License, Version 1.0.
+ *    (See accompanying file LICENSE or copy at
+ *          https://www.boost.org/LICENSE_1_0.txt)
+ */
+
+// An optional incremental-rescan fast path for `DirectoryStat`, for long-lived callers like an
+// editor or shell-prompt integration that call `status` repeatedly against the same worktree.
+// Instead of re-running `NtQueryDirectoryFile` over the whole tree on every call, a `Watcher`
+// registers a `ReadDirectoryChangesW` watch per directory and patches just the affected
+// `FileStat` in place when a notification arrives - the filesystem-watch-plus-incremental-model
+// approach editors like Zed use via fsevent, recast for the Win32 change-notification API
+// `DirectoryStat` already builds on. Compare `fsmonitor`, which gets the same kind of
+// "only what changed" answer from an external Watchman daemon instead of the OS directly.
+
+use crate::direntry::{FileKind, FileStat};
+use crate::dirstat::DirectoryStat;
+use crate::worktree::is_executable;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use winapi::um::fileapi::{CreateFileA, OPEN_EXISTING};
+use winapi::um::handleapi::CloseHandle;
+use winapi::um::winbase::{FILE_FLAG_BACKUP_SEMANTICS, ReadDirectoryChangesW};
+use winapi::um::winnt::{
+    FILE_LIST_DIRECTORY, FILE_NOTIFY_CHANGE_FILE_NAME, FILE_NOTIFY_CHANGE_LAST_WRITE,
+    FILE_NOTIFY_CHANGE_SIZE, FILE_NOTIFY_INFORMATION, FILE_SHARE_DELETE, FILE_SHARE_READ,
+    FILE_SHARE_WRITE, HANDLE,
+};
+
+/// Keeps a warm, per-directory `DirectoryStat` cache for one or more directories under a
+/// worktree, updated incrementally via `ReadDirectoryChangesW` instead of being rescanned on
+/// every `directory_stat` call.
+///
+/// Only directories explicitly handed to `watch` are kept warm; a directory discovered later
+/// (e.g. a newly created subdirectory) needs its own `watch` call before it benefits from this
+/// cache, and `directory_stat` transparently falls back to a fresh `DirectoryStat::new` scan for
+/// anything not yet watched, so correctness never depends on every directory being registered.
+pub struct Watcher {
+    cache: Arc<Mutex<HashMap<String, DirectoryStat>>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl Watcher {
+    pub fn new() -> Watcher {
+        Watcher {
+            cache: Arc::new(Mutex::new(HashMap::new())),
+            workers: vec![],
+        }
+    }
+
+    /// Starts watching `path`, priming the cache with an initial `DirectoryStat::new` scan and
+    /// spawning a background thread that patches just the changed entry on every subsequent
+    /// notification.
+    pub fn watch(&mut self, path: &Path) {
+        let directory = path.to_str().unwrap().to_string();
+        let initial = DirectoryStat::new(path);
+        self.cache
+            .lock()
+            .unwrap()
+            .insert(directory.clone(), initial);
+
+        let cache = Arc::clone(&self.cache);
+        let path = path.to_path_buf();
+        self.workers
+            .push(std::thread::spawn(move || Watcher::watch_loop(path, directory, cache)));
+    }
+
+    /// Returns the warm `DirectoryStat` for `path` if it's being watched, without touching the
+    /// filesystem; otherwise falls back to a one-off `DirectoryStat::new` scan.
+    pub fn directory_stat(&self, path: &Path) -> DirectoryStat {
+        let directory = path.to_str().unwrap();
+        match self.cache.lock().unwrap().get(directory) {
+            Some(stat) => stat.clone(),
+            None => DirectoryStat::new(path),
+        }
+    }
+
+    fn watch_loop(path: PathBuf, directory: String, cache: Arc<Mutex<HashMap<String, DirectoryStat>>>) {
+        let handle = Watcher::open_directory(&path);
+        if handle.is_null() {
+            return;
+        }
+
+        let mut buffer: [u8; 4096] = [0; 4096];
+        loop {
+            let mut bytes_returned: u32 = 0;
+            let result = unsafe {
+                ReadDirectoryChangesW(
+                    handle,
+                    buffer.as_mut_ptr() as *mut winapi::ctypes::c_void,
+                    buffer.len() as u32,
+                    0, // this directory only, not the subtree - each subdirectory gets its own watch
+                    FILE_NOTIFY_CHANGE_LAST_WRITE | FILE_NOTIFY_CHANGE_FILE_NAME | FILE_NOTIFY_CHANGE_SIZE,
+                    &mut bytes_returned,
+                    std::ptr::null_mut(),
+                    None,
+                )
+            };
+            if result == 0 {
+                break;
+            }
+
+            let changes = Watcher::parse_notifications(&buffer[..bytes_returned as usize]);
+            let mut cache = cache.lock().unwrap();
+            if let Some(dir_stat) = cache.get_mut(&directory) {
+                for name in changes {
+                    Watcher::apply_change(&path, dir_stat, &name);
+                }
+            }
+        }
+
+        unsafe {
+            CloseHandle(handle);
+        }
+    }
+
+    fn open_directory(path: &Path) -> HANDLE {
+        let name = CString::new(path.to_str().unwrap()).unwrap();
+        unsafe {
+            CreateFileA(
+                name.as_ptr(),
+                FILE_LIST_DIRECTORY,
+                FILE_SHARE_WRITE | FILE_SHARE_READ | FILE_SHARE_DELETE,
+                std::ptr::null_mut(),
+                OPEN_EXISTING,
+                FILE_FLAG_BACKUP_SEMANTICS,
+                std::ptr::null_mut(),
+            )
+        }
+    }
+
+    // A single `ReadDirectoryChangesW` result can report several changes in one buffer, chained
+    // via `NextEntryOffset`; only the file name is pulled out here, since `apply_change` re-stats
+    // the file directly from disk rather than trusting the notification's own action code - a
+    // rename, for instance, arrives as two separate entries (old name, new name), and re-stating
+    // both by name handles that the same way an add-then-remove would.
+    fn parse_notifications(buffer: &[u8]) -> Vec<String> {
+        let mut names = vec![];
+        let mut offset = 0;
+        loop {
+            let (_head, body, _tail) =
+                unsafe { buffer[offset..].align_to::<FILE_NOTIFY_INFORMATION>() };
+            let info = &body[0];
+            let name_ptr = unsafe {
+                (info as *const FILE_NOTIFY_INFORMATION as *const u8)
+                    .add(std::mem::size_of::<u32>() * 3) as *const u16
+            };
+            let name_len_u16 = info.FileNameLength as usize / 2;
+            let name_slice = unsafe { std::slice::from_raw_parts(name_ptr, name_len_u16) };
+            if let Ok(name) = String::from_utf16(name_slice) {
+                names.push(name);
+            }
+            if info.NextEntryOffset == 0 {
+                break;
+            }
+            offset += info.NextEntryOffset as usize;
+        }
+        names
+    }
+
+    fn apply_change(dir_path: &Path, dir_stat: &mut DirectoryStat, name: &str) {
+        let full_path = dir_path.join(name);
+        // `symlink_metadata` (unlike `Path::metadata`) doesn't follow a symlink, so a symlink's
+        // own kind and target can be recorded here the same way `DirectoryStat::get_dir_stats`
+        // would on a full rescan, instead of silently re-stating the file it points at as a plain
+        // file and clobbering its classification in the warm cache.
+        match std::fs::symlink_metadata(&full_path) {
+            Ok(metadata) if metadata.file_type().is_symlink() => {
+                let link_target = std::fs::read_link(&full_path)
+                    .ok()
+                    .and_then(|target| target.to_str().map(str::to_string));
+                dir_stat.file_stats.insert(
+                    name.to_string(),
+                    FileStat {
+                        mtime: Watcher::mtime_nanos(&metadata),
+                        size: metadata.len() as u32,
+                        executable: is_executable(&metadata),
+                        kind: FileKind::SymLink,
+                        link_target,
+                    },
+                );
+            }
+            Ok(metadata) if metadata.is_file() => {
+                dir_stat.file_stats.insert(
+                    name.to_string(),
+                    FileStat {
+                        mtime: Watcher::mtime_nanos(&metadata),
+                        size: metadata.len() as u32,
+                        executable: is_executable(&metadata),
+                        kind: FileKind::Regular,
+                        link_target: None,
+                    },
+                );
+            }
+            // Gone, or turned into something that isn't a plain file or symlink (e.g. a
+            // directory) - either way it no longer belongs in this directory's `file_stats`.
+            _ => {
+                dir_stat.file_stats.remove(name);
+            }
+        }
+    }
+
+    fn mtime_nanos(metadata: &std::fs::Metadata) -> u128 {
+        metadata
+            .modified()
+            .unwrap()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_nanos()
+    }
+}
+
+impl Default for Watcher {
+    fn default() -> Watcher {
+        Watcher::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use temp_testdir::TempDir;
+
+    // Builds a `ReadDirectoryChangesW`-shaped buffer containing one `FILE_NOTIFY_INFORMATION`
+    // record per name in `names`, chained via `NextEntryOffset` the same way a real multi-change
+    // notification is.
+    fn build_notification_buffer(names: &[&str]) -> Vec<u8> {
+        let mut buffer = vec![];
+        for (i, name) in names.iter().enumerate() {
+            let wide: Vec<u16> = name.encode_utf16().collect();
+            let mut record = vec![];
+            record.extend_from_slice(&0u32.to_ne_bytes()); // NextEntryOffset, patched below
+            record.extend_from_slice(&0u32.to_ne_bytes()); // Action, unused by parse_notifications
+            record.extend_from_slice(&((wide.len() * 2) as u32).to_ne_bytes());
+            for unit in &wide {
+                record.extend_from_slice(&unit.to_ne_bytes());
+            }
+            while record.len() % 4 != 0 {
+                record.push(0);
+            }
+            let is_last = i == names.len() - 1;
+            let next_entry_offset = if is_last { 0 } else { record.len() as u32 };
+            record[0..4].copy_from_slice(&next_entry_offset.to_ne_bytes());
+            buffer.extend_from_slice(&record);
+        }
+        buffer
+    }
+
+    #[test]
+    fn test_parse_notifications_reads_a_single_entry() {
+        let buffer = build_notification_buffer(&["changed.txt"]);
+        let names = Watcher::parse_notifications(&buffer);
+        assert_eq!(names, vec!["changed.txt".to_string()]);
+    }
+
+    #[test]
+    fn test_parse_notifications_follows_next_entry_offset_across_several_entries() {
+        let buffer = build_notification_buffer(&["old_name.txt", "new_name.txt", "other.txt"]);
+        let names = Watcher::parse_notifications(&buffer);
+        assert_eq!(
+            names,
+            vec![
+                "old_name.txt".to_string(),
+                "new_name.txt".to_string(),
+                "other.txt".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_apply_change_records_a_plain_file() {
+        let temp_dir = TempDir::default();
+        std::fs::write(temp_dir.join("plain.txt"), "content").unwrap();
+
+        let mut dir_stat = DirectoryStat::default();
+        Watcher::apply_change(&temp_dir, &mut dir_stat, "plain.txt");
+
+        let stat = dir_stat.file_stats.get("plain.txt").unwrap();
+        assert_eq!(stat.kind, FileKind::Regular);
+        assert_eq!(stat.link_target, None);
+    }
+
+    #[test]
+    fn test_apply_change_classifies_a_symlink_and_captures_its_target() {
+        let temp_dir = TempDir::default();
+        std::fs::write(temp_dir.join("target.txt"), "content").unwrap();
+        std::os::windows::fs::symlink_file("target.txt", temp_dir.join("link")).unwrap();
+
+        let mut dir_stat = DirectoryStat::default();
+        Watcher::apply_change(&temp_dir, &mut dir_stat, "link");
+
+        let stat = dir_stat.file_stats.get("link").unwrap();
+        assert_eq!(stat.kind, FileKind::SymLink);
+        assert_eq!(stat.link_target.as_deref(), Some("target.txt"));
+    }
+
+    #[test]
+    fn test_apply_change_removes_a_deleted_entry() {
+        let temp_dir = TempDir::default();
+        let mut dir_stat = DirectoryStat::default();
+        dir_stat
+            .file_stats
+            .insert("gone.txt".to_string(), FileStat::default());
+
+        Watcher::apply_change(&temp_dir, &mut dir_stat, "gone.txt");
+
+        assert!(!dir_stat.file_stats.contains_key("gone.txt"));
+    }
+}
@@ -10,19 +10,42 @@ use pathdiff::diff_paths;
 use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
-use crate::direntry::{DirEntry, FileStat, ObjectType};
+use crate::direntry::{DirEntry, FileKind, FileStat, ObjectType};
 use crate::error::StatusError;
+use crate::fsmonitor::{query_watchman, FsmonitorKind, FsmonitorState};
 use crate::status::{Status, StatusEntry};
 use crate::{Index, TreeDiff};
 use git2::Repository;
 use ignore::gitignore::{Gitignore, GitignoreBuilder};
+use memmap2::Mmap;
+use sha1::{Digest, Sha1};
 use std::fs;
+use std::fs::File;
+use std::sync::mpsc;
 use std::time::UNIX_EPOCH;
 
+/// Mirrors git's `status.showUntrackedFiles` config / `-u`/`--untracked-files` flag: how deep
+/// to report untracked content.  `No` omits untracked paths entirely, `Normal` collapses an
+/// untracked directory to a single `dir/` entry (git's own default), and `All` recurses into it
+/// and reports every file inside individually.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum UntrackedMode {
+    No,
+    Normal,
+    All,
+}
+
+impl Default for UntrackedMode {
+    fn default() -> Self {
+        UntrackedMode::Normal
+    }
+}
+
 #[derive(Debug)]
 pub struct ReadDirEntry {
     pub name: String,
     pub is_dir: bool,
+    pub is_symlink: bool,
     pub process: bool,
     pub stat: FileStat,
     pub parent_path: Arc<Path>,
@@ -35,12 +58,43 @@ impl ReadDirEntry {
     }
 }
 
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Clone)]
 struct ReadWorktreeState {
     path: PathBuf,
     index: Arc<Index>,
-    changed_files: Arc<Mutex<Vec<StatusEntry>>>,
+    // One batch of changes per directory is sent as they're discovered, rather than collecting
+    // everything into a single shared `Vec`, so a streaming caller can see results well before
+    // the whole worktree has been walked.
+    changed_files: mpsc::Sender<Vec<StatusEntry>>,
+    // A single permission error, a directory that vanishes mid-scan, or a broken submodule
+    // shouldn't abort the whole walk; failures are funneled back through this sink instead,
+    // tagged with the path that caused them, and surfaced on the returned `WorkTree`.
+    errors: mpsc::Sender<(PathBuf, StatusError)>,
     ignores: Vec<Arc<Gitignore>>,
+    // How many submodules deep this walk already is.  Only ever incremented when recursing into
+    // a submodule's own worktree, and checked against `MAX_SUBMODULE_DEPTH` so a submodule that
+    // (directly or indirectly) contains itself can't recurse forever.
+    submodule_depth: usize,
+    untracked_mode: UntrackedMode,
+}
+
+// A submodule referencing itself, or two submodules referencing each other, would otherwise
+// recurse without bound; this is far deeper than any legitimate submodule nesting.
+const MAX_SUBMODULE_DEPTH: usize = 10;
+
+// Windows has no concept of an owner-executable permission bit, so there's nothing meaningful
+// to report there; symlink/regular-file mismatches are still caught via `is_symlink` regardless.
+// pub (rather than private) since `watcher::Watcher` reuses this same executable-bit check to
+// build a `FileStat` for a single changed file instead of duplicating it.
+#[cfg(unix)]
+pub fn is_executable(metadata: &std::fs::Metadata) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o100 != 0
+}
+
+#[cfg(not(unix))]
+pub fn is_executable(_metadata: &std::fs::Metadata) -> bool {
+    false
 }
 
 fn read_dir(
@@ -49,23 +103,59 @@ fn read_dir(
     depth: usize,
     scope: &rayon::Scope,
 ) {
+    let dir_iter = match fs::read_dir(path) {
+        Ok(dir_iter) => dir_iter,
+        Err(err) => {
+            let _ = read_dir_state
+                .errors
+                .send((path.to_path_buf(), err.into()));
+            return;
+        }
+    };
+
     let mut files = vec![];
     let parent_path = Arc::from(path);
-    for entry in fs::read_dir(path).unwrap() {
-        let entry = entry.unwrap();
-        let metadata = entry.metadata().unwrap();
+    for entry in dir_iter {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                let _ = read_dir_state
+                    .errors
+                    .send((path.to_path_buf(), err.into()));
+                continue;
+            }
+        };
+        let entry_path = entry.path();
+        let (file_type, metadata) = match entry.file_type().and_then(|t| Ok((t, entry.metadata()?))) {
+            Ok(pair) => pair,
+            Err(err) => {
+                let _ = read_dir_state.errors.send((entry_path, err.into()));
+                continue;
+            }
+        };
         files.push(ReadDirEntry {
-            is_dir: entry.file_type().unwrap().is_dir(),
+            is_dir: file_type.is_dir(),
+            is_symlink: file_type.is_symlink(),
             name: entry.file_name().to_str().unwrap().to_string(),
             process: true,
             stat: FileStat {
+                // Full nanosecond precision, not just whole seconds, so two writes within the
+                // same second still compare as different - the "racy git" checks in
+                // `process_tracked_item` (`dir_entry.stat.mtime >= index_mtime`/`index_entry.stat.mtime`) depend on
+                // that precision to tell a same-second edit apart from a genuinely stale stat.
                 mtime: metadata
                     .modified()
                     .unwrap()
                     .duration_since(UNIX_EPOCH)
                     .unwrap()
-                    .as_secs() as u32,
+                    .as_nanos(),
                 size: metadata.len() as u32,
+                executable: is_executable(&metadata),
+                // The entry's own `file_type().is_symlink()` already distinguishes a symlink
+                // here (see `ReadDirEntry::is_symlink` above); `FileKind` is only populated by
+                // `DirectoryStat` and by `watcher::Watcher`'s patches to its cached entries.
+                kind: FileKind::Regular,
+                link_target: None,
             },
             parent_path: Arc::clone(&parent_path),
             depth,
@@ -96,6 +186,10 @@ fn read_dir(
 pub struct WorkTree {
     path: String,
     pub entries: Vec<StatusEntry>,
+    // Paths that couldn't be statted, read, or opened as a submodule repo while walking the
+    // worktree.  `entries` still reflects everything that _could_ be compared, so a caller can
+    // choose to report these as warnings rather than fail the whole status.
+    pub errors: Vec<(PathBuf, StatusError)>,
 }
 
 impl WorkTree {
@@ -106,29 +200,230 @@ impl WorkTree {
     ///     a git repo
     /// * `index` - The index to compare against
     pub fn diff_against_index(path: &Path, index: Index) -> Result<WorkTree, StatusError> {
-        let changed_files = Arc::new(Mutex::new(vec![]));
+        WorkTree::diff_against_index_at_submodule_depth(path, index, 0, UntrackedMode::Normal, None)
+    }
+
+    /// Like `diff_against_index`, but `untracked_mode` controls how untracked directories are
+    /// reported (see `UntrackedMode`) instead of always collapsing them.
+    pub fn diff_against_index_with_untracked_mode(
+        path: &Path,
+        index: Index,
+        untracked_mode: UntrackedMode,
+    ) -> Result<WorkTree, StatusError> {
+        WorkTree::diff_against_index_at_submodule_depth(path, index, 0, untracked_mode, None)
+    }
 
-        WorkTree::scoped_diff(path, index, &changed_files);
+    /// Like `diff_against_index`, but bounds the directory-walking/content-hashing work to a
+    /// dedicated pool of `worker_count` threads instead of whatever else is already scheduled on
+    /// rayon's shared global pool.  Useful when a caller wants a predictable amount of CPU spent
+    /// on a status scan rather than however many cores happen to be free.  Submodules nested
+    /// underneath are still diffed on the global pool; they're comparatively small and not worth
+    /// plumbing the bound through another layer of recursion.
+    pub fn diff_against_index_with_worker_count(
+        path: &Path,
+        index: Index,
+        worker_count: usize,
+    ) -> Result<WorkTree, StatusError> {
+        WorkTree::diff_against_index_at_submodule_depth(
+            path,
+            index,
+            0,
+            UntrackedMode::Normal,
+            Some(worker_count),
+        )
+    }
+
+    // Shared by the public `diff_against_index` (depth 0) and submodule recursion (depth
+    // incremented by the nearest enclosing `submodule_status`), so `MAX_SUBMODULE_DEPTH` bounds
+    // nesting no matter how many superprojects deep a submodule is diffed from.
+    fn diff_against_index_at_submodule_depth(
+        path: &Path,
+        index: Index,
+        submodule_depth: usize,
+        untracked_mode: UntrackedMode,
+        worker_count: Option<usize>,
+    ) -> Result<WorkTree, StatusError> {
+        let mut entries = vec![];
+        let mut errors = vec![];
+        WorkTree::diff_against_index_streaming_at_submodule_depth(
+            path,
+            index,
+            usize::MAX,
+            |batch| entries.extend_from_slice(batch),
+            |err_path, err| errors.push((err_path, err)),
+            submodule_depth,
+            untracked_mode,
+            worker_count,
+        )?;
+
+        // The walk's own directories run concurrently and flush batches in whatever order they
+        // finish, so without this the entries here (and the rendering that iterates them
+        // directly, e.g. `write_short_unstaged`) would have a path ordering that varies run to
+        // run.  Sorting once here, rather than in every renderer, keeps that determinism in one
+        // place and matches the order the old, effectively single-threaded walk produced.
+        entries.sort_by(|a, b| a.name.cmp(&b.name));
+        errors.sort_by(|a, b| a.0.cmp(&b.0));
 
         let work_tree = WorkTree {
             path: String::from(path.to_str().unwrap()),
-            entries: changed_files.lock().unwrap().to_vec(),
+            entries,
+            errors,
         };
         Ok(work_tree)
     }
 
-    fn scoped_diff(path: &Path, index: Index, changed_files: &Arc<Mutex<Vec<StatusEntry>>>) {
+    /// Compares an index to the on disk work tree, flushing batches of up to `batch_size` changes
+    /// to `on_batch` as they're discovered rather than waiting for the whole worktree to be
+    /// walked.  On a large repo this lets a caller start reporting changes within moments instead
+    /// of after several seconds of silence.
+    ///
+    /// A path that can't be statted, read, or (for a submodule) opened as a repo doesn't abort
+    /// the walk; it's passed to `on_error` tagged with the path that caused it, and the walk
+    /// continues with everything else.
+    ///
+    /// # Arguments
+    /// * `path` - The path to a git repo.  This logic will _not_ search up parent directories for
+    ///     a git repo
+    /// * `index` - The index to compare against
+    /// * `batch_size` - The maximum number of changes passed to `on_batch` at a time
+    /// * `on_batch` - Called with each batch of changes as they're discovered
+    /// * `on_error` - Called with each path, and the error encountered statting/reading/diffing it
+    pub fn diff_against_index_streaming(
+        path: &Path,
+        index: Index,
+        batch_size: usize,
+        on_batch: impl FnMut(&[StatusEntry]),
+        on_error: impl FnMut(PathBuf, StatusError),
+    ) -> Result<(), StatusError> {
+        WorkTree::diff_against_index_streaming_at_submodule_depth(
+            path,
+            index,
+            batch_size,
+            on_batch,
+            on_error,
+            0,
+            UntrackedMode::Normal,
+            None,
+        )
+    }
+
+    fn diff_against_index_streaming_at_submodule_depth(
+        path: &Path,
+        index: Index,
+        batch_size: usize,
+        mut on_batch: impl FnMut(&[StatusEntry]),
+        mut on_error: impl FnMut(PathBuf, StatusError),
+        submodule_depth: usize,
+        untracked_mode: UntrackedMode,
+        worker_count: Option<usize>,
+    ) -> Result<(), StatusError> {
+        // A batch size of 0 would never flush, so treat it the same as 1.
+        let batch_size = batch_size.max(1);
+        let (sender, receiver) = mpsc::channel();
+        let (error_sender, error_receiver) = mpsc::channel();
+        let path = PathBuf::from(path);
+        let handle = std::thread::spawn(move || {
+            WorkTree::scoped_diff(
+                &path,
+                index,
+                sender,
+                error_sender,
+                submodule_depth,
+                untracked_mode,
+                worker_count,
+            );
+        });
+
+        let mut pending = Vec::with_capacity(batch_size.min(4096));
+        for directory_batch in receiver {
+            pending.extend(directory_batch);
+            while pending.len() >= batch_size {
+                let flushed: Vec<StatusEntry> = pending.drain(..batch_size).collect();
+                on_batch(&flushed);
+            }
+        }
+        if !pending.is_empty() {
+            on_batch(&pending);
+        }
+        for (err_path, err) in error_receiver {
+            on_error(err_path, err);
+        }
+
+        // The producer thread only panics if one of the workers it spawned did, in which case
+        // there's nothing sensible left to return to the caller.
+        handle.join().unwrap();
+        Ok(())
+    }
+
+    /// Compares an index to the on disk work tree, using a filesystem-monitor fast path when one
+    /// is configured and `state` holds a clock from a previous run.
+    ///
+    /// When the fast path is unavailable or unusable (no fsmonitor configured, no prior clock, or
+    /// the monitor rejects the clock as stale) this falls back to the full walk `diff_against_index`
+    /// does, so correctness never depends on the watcher being present or caught up.
+    pub fn diff_against_index_with_fsmonitor(
+        path: &Path,
+        index: Index,
+        kind: FsmonitorKind,
+        mut state: FsmonitorState,
+    ) -> Result<(WorkTree, FsmonitorState), StatusError> {
+        if kind == FsmonitorKind::Watchman && state.clock.is_some() {
+            if let Ok((modified, deleted)) = query_watchman(path, &mut state) {
+                let changed_files = Arc::new(Mutex::new(vec![]));
+                for name in &deleted {
+                    if let Some(index_entry) = find_index_entry(&index, name) {
+                        if let Some(entry) = process_deleted_item(index_entry) {
+                            changed_files.lock().unwrap().push(entry);
+                        }
+                    }
+                }
+                restate_paths(&index, &state, &modified, &changed_files);
+
+                let work_tree = WorkTree {
+                    path: String::from(path.to_str().unwrap()),
+                    entries: changed_files.lock().unwrap().to_vec(),
+                    // find_index_entry/restate_paths/process_deleted_item only work from the
+                    // paths and stats Watchman already reported, without touching the filesystem,
+                    // so there's nothing here that can fail the way a fresh `fs::read_dir` walk can.
+                    errors: vec![],
+                };
+                return Ok((work_tree, state));
+            }
+        }
+
+        let work_tree = WorkTree::diff_against_index(path, index)?;
+        Ok((work_tree, state))
+    }
+
+    fn scoped_diff(
+        path: &Path,
+        index: Index,
+        changed_files: mpsc::Sender<Vec<StatusEntry>>,
+        errors: mpsc::Sender<(PathBuf, StatusError)>,
+        submodule_depth: usize,
+        untracked_mode: UntrackedMode,
+        worker_count: Option<usize>,
+    ) {
         let (global_ignore, _) = GitignoreBuilder::new("").build_global();
         let mut read_dir_state = ReadWorktreeState {
             path: PathBuf::from(path),
             index: Arc::new(index),
-            changed_files: Arc::clone(changed_files),
+            changed_files,
+            errors,
             ignores: vec![Arc::new(global_ignore)],
+            submodule_depth,
+            untracked_mode,
         };
 
-        rayon::scope(|s| {
-            read_dir(path, &mut read_dir_state, 1, s);
-        });
+        let walk = |s: &rayon::Scope| read_dir(path, &mut read_dir_state, 1, s);
+        match worker_count {
+            Some(n) => rayon::ThreadPoolBuilder::new()
+                .num_threads(n)
+                .build()
+                .unwrap()
+                .scope(walk),
+            None => rayon::scope(walk),
+        }
     }
 }
 
@@ -138,7 +433,20 @@ fn process_directory(
     entries: &mut Vec<ReadDirEntry>,
     scope: &rayon::Scope,
 ) {
-    update_ignores(path, &mut read_dir_state.ignores);
+    if let Err(err) = update_ignores(path, &mut read_dir_state.ignores) {
+        let _ = read_dir_state.errors.send((path.to_path_buf(), err));
+    }
+    if read_dir_state.untracked_mode == UntrackedMode::All {
+        // A directory with no index entries of its own (an untracked directory, which is only
+        // walked into in `All` mode) has every file in it reported as new.
+        let relative_path = diff_paths(path, &read_dir_state.path).unwrap();
+        let unix_path = relative_path.to_str().unwrap().replace("\\", "/");
+        if !read_dir_state.index.entries.contains_key(&unix_path) {
+            let index = &read_dir_state.index;
+            get_file_deltas(entries, &[], index, read_dir_state, scope);
+            return;
+        }
+    }
 
     let index = &read_dir_state.index;
     let relative_path = diff_paths(path, &read_dir_state.path).unwrap();
@@ -154,15 +462,16 @@ fn process_directory(
     }
 }
 
-fn update_ignores(path: &Path, ignores: &mut Vec<Arc<Gitignore>>) {
+fn update_ignores(path: &Path, ignores: &mut Vec<Arc<Gitignore>>) -> Result<(), StatusError> {
     let ignore_file = path.join(".gitignore");
     if !ignore_file.exists() {
-        return;
+        return Ok(());
     }
     let mut builder = GitignoreBuilder::new(path);
     builder.add(ignore_file);
-    let ignore = builder.build().unwrap();
+    let ignore = builder.build()?;
     ignores.insert(0, Arc::new(ignore));
+    Ok(())
 }
 
 fn get_file_deltas(
@@ -173,7 +482,9 @@ fn get_file_deltas(
     scope: &rayon::Scope,
 ) {
     // println!("The worktree {:?}", worktree);
-    let file_changes = &read_dir_state.changed_files;
+    // Buffered locally and sent as a single batch once this directory's entries are done, rather
+    // than handed to the channel one at a time.
+    let mut batch = vec![];
     let mut worktree_iter = worktree.iter_mut();
     let mut index_iter = index_entry.iter();
     let mut worktree_file = worktree_iter.next();
@@ -184,28 +495,46 @@ fn get_file_deltas(
                 Ordering::Equal => {
                     if let Some(entry) = process_tracked_item(w_file, i_file, read_dir_state, scope)
                     {
-                        file_changes.lock().unwrap().push(entry);
+                        batch.push(entry);
                     }
                     index_file = index_iter.next();
                     worktree_file = worktree_iter.next();
                 }
                 Ordering::Less => {
-                    if let Some(entry) = process_new_item(w_file, index, &read_dir_state.ignores) {
-                        file_changes.lock().unwrap().push(entry);
+                    match process_new_item(
+                        w_file,
+                        index,
+                        &read_dir_state.ignores,
+                        read_dir_state.untracked_mode,
+                    ) {
+                        Ok(Some(entry)) => batch.push(entry),
+                        Ok(None) => {}
+                        Err(err) => {
+                            let _ = read_dir_state.errors.send((w_file.path(), err));
+                        }
                     }
                     worktree_file = worktree_iter.next();
                 }
                 Ordering::Greater => {
                     if let Some(entry) = process_deleted_item(i_file) {
-                        file_changes.lock().unwrap().push(entry);
+                        batch.push(entry);
                     }
                     worktree_file = Some(w_file);
                     index_file = index_iter.next();
                 }
             },
             None => {
-                if let Some(entry) = process_new_item(w_file, index, &read_dir_state.ignores) {
-                    file_changes.lock().unwrap().push(entry);
+                match process_new_item(
+                    w_file,
+                    index,
+                    &read_dir_state.ignores,
+                    read_dir_state.untracked_mode,
+                ) {
+                    Ok(Some(entry)) => batch.push(entry),
+                    Ok(None) => {}
+                    Err(err) => {
+                        let _ = read_dir_state.errors.send((w_file.path(), err));
+                    }
                 }
                 worktree_file = worktree_iter.next();
             }
@@ -213,10 +542,16 @@ fn get_file_deltas(
     }
     while let Some(i_file) = index_file {
         if let Some(entry) = process_deleted_item(i_file) {
-            file_changes.lock().unwrap().push(entry);
+            batch.push(entry);
         }
         index_file = index_iter.next();
     }
+
+    if !batch.is_empty() {
+        // The receiving end is dropped if the caller's streaming consumer returns early; there's
+        // nothing for a worker thread to do about that, so silently stop reporting.
+        let _ = read_dir_state.changed_files.send(batch);
+    }
 }
 
 fn process_deleted_item(index_entry: &DirEntry) -> Option<StatusEntry> {
@@ -231,6 +566,46 @@ fn process_deleted_item(index_entry: &DirEntry) -> Option<StatusEntry> {
     })
 }
 
+fn find_index_entry<'a>(index: &'a Index, full_path: &str) -> Option<&'a DirEntry> {
+    let (directory, name) = match full_path.rfind('/') {
+        Some(i) => (&full_path[..i], &full_path[i + 1..]),
+        None => ("", full_path),
+    };
+    index.entries.get(directory)?.iter().find(|e| e.name == name)
+}
+
+// Restates the paths the fsmonitor reported as modified against their index entries, using the
+// stat it already gave us instead of calling back out to the filesystem.
+fn restate_paths(
+    index: &Index,
+    state: &FsmonitorState,
+    paths: &[String],
+    changed_files: &Arc<Mutex<Vec<StatusEntry>>>,
+) {
+    for name in paths {
+        let stat = match state.files.get(name) {
+            Some(stat) => stat,
+            None => continue,
+        };
+        match find_index_entry(index, name) {
+            Some(index_entry) => {
+                if *stat != index_entry.stat {
+                    changed_files.lock().unwrap().push(StatusEntry {
+                        name: name.clone(),
+                        state: Status::Modified,
+                    });
+                }
+            }
+            None => {
+                changed_files.lock().unwrap().push(StatusEntry {
+                    name: name.clone(),
+                    state: Status::New,
+                });
+            }
+        }
+    }
+}
+
 fn get_relative_entry_path_name(entry: &ReadDirEntry) -> String {
     let path = entry.path();
     let root = path.ancestors().nth(entry.depth).unwrap();
@@ -242,41 +617,57 @@ fn process_new_item(
     dir_entry: &mut ReadDirEntry,
     index: &Arc<Index>,
     ignores: &[Arc<Gitignore>],
-) -> Option<StatusEntry> {
+    untracked_mode: UntrackedMode,
+) -> Result<Option<StatusEntry>, StatusError> {
     let mut name = get_relative_entry_path_name(dir_entry);
     if dir_entry.is_dir {
         if index.entries.contains_key(&name) {
-            return None;
+            return Ok(None);
+        }
+        // In `All` mode the directory's contents are walked and reported individually instead
+        // of collapsing to one `dir/` entry, so recursion must stay enabled.
+        if untracked_mode != UntrackedMode::All {
+            dir_entry.process = false;
         }
-        dir_entry.process = false;
     }
 
-    if is_ignored(dir_entry, &name, ignores) {
-        return None;
+    if is_ignored(dir_entry, &name, ignores)? {
+        return Ok(None);
+    }
+
+    if untracked_mode == UntrackedMode::No {
+        return Ok(None);
     }
 
-    // Done after ignore as ignore doesn't handle trailing "/"
     if dir_entry.is_dir {
+        if untracked_mode == UntrackedMode::All {
+            return Ok(None);
+        }
+        // Done after ignore as ignore doesn't handle trailing "/"
         name.push('/');
     }
 
-    Some(StatusEntry {
+    Ok(Some(StatusEntry {
         name,
         state: Status::New,
-    })
+    }))
 }
 
-fn is_ignored(entry: &mut ReadDirEntry, name: &str, ignores: &[Arc<Gitignore>]) -> bool {
+fn is_ignored(
+    entry: &mut ReadDirEntry,
+    name: &str,
+    ignores: &[Arc<Gitignore>],
+) -> Result<bool, StatusError> {
     let is_dir = entry.is_dir;
     for ignore in ignores {
         let matched = ignore.matched_path_or_any_parents(name, is_dir);
 
         // Whitelisting happens when a pattern is added back to valid files via the preceding "!"
         if matched.is_whitelist() {
-            return false;
+            return Ok(false);
         }
         if matched.is_ignore() {
-            return true;
+            return Ok(true);
         }
     }
 
@@ -285,16 +676,20 @@ fn is_ignored(entry: &mut ReadDirEntry, name: &str, ignores: &[Arc<Gitignore>])
     if is_dir {
         let path = entry.path();
         let root = path.ancestors().nth(entry.depth).unwrap();
-        return !directory_has_one_trackable_file(&root, &path, &ignores);
+        return Ok(!directory_has_one_trackable_file(&root, &path, ignores)?);
     }
-    false
+    Ok(false)
 }
 
-fn directory_has_one_trackable_file(root: &Path, dir: &Path, ignores: &[Arc<Gitignore>]) -> bool {
-    for entry in fs::read_dir(dir).unwrap() {
-        let entry = entry.unwrap();
+fn directory_has_one_trackable_file(
+    root: &Path,
+    dir: &Path,
+    ignores: &[Arc<Gitignore>],
+) -> Result<bool, StatusError> {
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
         let path = entry.path();
-        let is_dir = entry.file_type().unwrap().is_dir();
+        let is_dir = entry.file_type()?.is_dir();
         if !is_dir {
             let relative_path = diff_paths(&path, root).unwrap();
             let name = relative_path.to_str().unwrap().replace("\\", "/");
@@ -311,13 +706,13 @@ fn directory_has_one_trackable_file(root: &Path, dir: &Path, ignores: &[Arc<Giti
                 }
             }
             if !ignored {
-                return true;
+                return Ok(true);
             }
-        } else if directory_has_one_trackable_file(root, &path, ignores) {
-            return true;
+        } else if directory_has_one_trackable_file(root, &path, ignores)? {
+            return Ok(true);
         }
     }
-    false
+    Ok(false)
 }
 
 fn submodule_status(
@@ -328,47 +723,83 @@ fn submodule_status(
 ) {
     let name = get_relative_entry_path_name(dir_entry);
     let path = dir_entry.path();
+
+    if read_dir_state.submodule_depth >= MAX_SUBMODULE_DEPTH {
+        let message = format!(
+            "Submodule at {:?} is nested {} levels deep, which exceeds the limit of {}; \
+             assuming a submodule cycle and not descending further",
+            path, read_dir_state.submodule_depth, MAX_SUBMODULE_DEPTH
+        );
+        let _ = read_dir_state.errors.send((path, StatusError { message }));
+        return;
+    }
+
     let sha = index_entry.sha.to_vec();
-    let changed_clone = Arc::clone(&read_dir_state.changed_files);
+    let submodule_depth = read_dir_state.submodule_depth + 1;
+    let untracked_mode = read_dir_state.untracked_mode;
+    let changed_clone = read_dir_state.changed_files.clone();
+    let errors_clone = read_dir_state.errors.clone();
     scope.spawn(move |_s| {
-        submodule_spawned_status(name, path.to_str().unwrap().to_string(), sha, changed_clone)
+        if let Err(err) = submodule_spawned_status(
+            &name,
+            &path,
+            &sha,
+            &changed_clone,
+            submodule_depth,
+            untracked_mode,
+        ) {
+            let _ = errors_clone.send((path, err));
+        }
     });
 }
 
 fn submodule_spawned_status(
-    name: String,
-    path: String,
-    index_sha: Vec<u8>,
-    changed_files: Arc<Mutex<Vec<StatusEntry>>>,
-) {
-    let path = Path::new(&path);
-    let repo = Repository::open(&path).unwrap();
+    name: &str,
+    path: &Path,
+    index_sha: &[u8],
+    changed_files: &mpsc::Sender<Vec<StatusEntry>>,
+    submodule_depth: usize,
+    untracked_mode: UntrackedMode,
+) -> Result<(), StatusError> {
+    let repo = Repository::open(path)?;
     let repo_path = repo.path();
     let index_file = repo_path.join("index");
-    let index = Index::new(&index_file).unwrap();
-
-    let workdir = repo.workdir().unwrap();
-    let work_tree_diff = WorkTree::diff_against_index(workdir, index).unwrap();
+    let index = Index::new(&index_file)?;
+
+    let workdir = repo.workdir().ok_or_else(|| StatusError {
+        message: format!("Submodule at {:?} has no working directory", path),
+    })?;
+    let work_tree_diff = WorkTree::diff_against_index_at_submodule_depth(
+        workdir,
+        index,
+        submodule_depth,
+        untracked_mode,
+        None,
+    )?;
     let index_diff = TreeDiff::diff_against_index_with_repo(&repo);
 
-    // This isn't quite true, but close enough for now
     let modified_content = !index_diff.entries.is_empty();
     let untracked_content = !work_tree_diff.entries.is_empty();
+    let new_commits = index_sha != repo.head()?.peel_to_commit()?.id().as_bytes();
 
-    let new_commits = index_sha
-        != repo
-            .head()
-            .unwrap()
-            .peel_to_commit()
-            .unwrap()
-            .id()
-            .as_bytes();
-    if modified_content || untracked_content || new_commits {
-        changed_files.lock().unwrap().push(StatusEntry {
-            name,
-            state: Status::Modified,
-        });
+    let mut reasons = vec![];
+    if new_commits {
+        reasons.push("new commits");
+    }
+    if modified_content {
+        reasons.push("modified content");
     }
+    if untracked_content {
+        reasons.push("untracked content");
+    }
+
+    if !reasons.is_empty() {
+        let _ = changed_files.send(vec![StatusEntry {
+            name: name.to_string(),
+            state: Status::Modified(Some(reasons.join(", "))),
+        }]);
+    }
+    Ok(())
 }
 
 fn process_tracked_item(
@@ -384,20 +815,89 @@ fn process_tracked_item(
         return None;
     }
 
+    if dir_entry.is_symlink != (index_entry.object_type == ObjectType::SymLink) {
+        let name = get_relative_entry_path_name(dir_entry);
+        return Some(StatusEntry {
+            name,
+            state: Status::TypeChange,
+        });
+    }
+
     if dir_entry.stat != index_entry.stat {
         let name = get_relative_entry_path_name(dir_entry);
+
+        // A file that was only touched (same size, or an mtime at or after the entry's recorded one) can
+        // still have its old content.  Rather than trust the stat alone, fall back to comparing
+        // blob hashes in those cases so the common, genuinely-unchanged case isn't misreported.
+        let racy = dir_entry.stat.size == index_entry.stat.size
+            || dir_entry.stat.mtime >= index_entry.stat.mtime;
+        if racy && !content_changed(dir_entry, index_entry) {
+            return None;
+        }
+
         return Some(StatusEntry {
             name,
             state: Status::Modified,
         });
     }
+
+    // The stat matches the index entry exactly, which is ordinarily enough to call the file
+    // clean without opening it - the fast path that makes the common "nothing changed" case an
+    // O(stat) operation.  The one case that still needs a real look at the content is "racy
+    // git": if the file's mtime is at or after the moment the index itself was last written, an
+    // edit landing in that same tick could leave an identical stat behind, so the stat alone
+    // can't be trusted there.
+    if let Some(index_mtime) = read_dir_state.index.index_mtime {
+        if dir_entry.stat.mtime >= index_mtime && content_changed(dir_entry, index_entry) {
+            let name = get_relative_entry_path_name(dir_entry);
+            return Some(StatusEntry {
+                name,
+                state: Status::Modified,
+            });
+        }
+    }
     None
 }
 
+// Computes the git blob object id of the file at `dir_entry`'s path and compares it to
+// `index_entry.sha`.  Any error reading the file is treated as a content change, since a real
+// change is the far more useful assumption for status reporting than silently hiding the file.
+fn content_changed(dir_entry: &ReadDirEntry, index_entry: &DirEntry) -> bool {
+    match blob_sha1(&dir_entry.path()) {
+        Ok(sha) => sha != index_entry.sha,
+        Err(_) => true,
+    }
+}
+
+// `pub` (rather than private) since `DirTreeDiff` reuses this same blob-hashing fallback for its
+// own racy-stat comparison instead of duplicating it.
+pub fn blob_sha1(path: &Path) -> std::io::Result<[u8; 20]> {
+    let file = File::open(path)?;
+    let len = file.metadata()?.len();
+    if len == 0 {
+        return Ok(blob_sha1_of_bytes(&[]));
+    }
+
+    // Memory-mapped so a clean comparison of a large, unmodified file doesn't pay to copy it
+    // into a fresh buffer first.
+    let contents = unsafe { Mmap::map(&file)? };
+    Ok(blob_sha1_of_bytes(&contents))
+}
+
+/// Computes the git blob object id of `contents` directly, without reading it from a file -
+/// shared by `blob_sha1` for on-disk file bytes and by `DirTreeDiff`'s symlink comparison, which
+/// hashes a link-target string read via `FSCTL_GET_REPARSE_POINT` rather than a file's contents.
+pub fn blob_sha1_of_bytes(contents: &[u8]) -> [u8; 20] {
+    let mut hasher = Sha1::new();
+    hasher.update(format!("blob {}\0", contents.len()).as_bytes());
+    hasher.update(contents);
+    hasher.finalize().into()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use git2::{Repository, Signature, Time};
+    use git2::{Repository, Signature, SubmoduleUpdateOptions, Time};
     use std::fs;
     use temp_testdir::TempDir;
 
@@ -443,9 +943,11 @@ mod tests {
     fn test_diff_against_index_a_file_modified_size() {
         let entry_name = "simple_file.txt";
         let temp_dir = TempDir::default();
-        let mut index = test_repo(&temp_dir, &vec![Path::new(entry_name)]);
-        let dir_entries = index.entries.get_mut("").unwrap();
-        dir_entries[0].stat.size += 1;
+        let index = test_repo(&temp_dir, &vec![Path::new(entry_name)]);
+        let file_path = temp_dir.join(entry_name);
+        let mut contents = fs::read(&file_path).unwrap();
+        contents.extend_from_slice(b"more content");
+        fs::write(&file_path, contents).unwrap();
         let value = WorkTree::diff_against_index(&temp_dir, index).unwrap();
         let entries = vec![StatusEntry {
             name: entry_name.to_string(),
@@ -455,12 +957,63 @@ mod tests {
     }
 
     #[test]
-    fn test_diff_against_index_a_file_modified_mstat() {
+    fn test_diff_against_index_a_file_modified_content_same_size() {
+        let entry_name = "simple_file.txt";
+        let temp_dir = TempDir::default();
+        let index = test_repo(&temp_dir, &vec![Path::new(entry_name)]);
+        let file_path = temp_dir.join(entry_name);
+        let contents = fs::read(&file_path).unwrap();
+        let flipped: Vec<u8> = contents.iter().map(|b| b ^ 0xff).collect();
+        fs::write(&file_path, flipped).unwrap();
+        let value = WorkTree::diff_against_index(&temp_dir, index).unwrap();
+        let entries = vec![StatusEntry {
+            name: entry_name.to_string(),
+            state: Status::Modified,
+        }];
+        assert_eq!(value.entries, entries);
+    }
+
+    // A racy mtime bump on its own, with the file's content left untouched, used to be reported
+    // as `Modified` since it only compared stats.  The content-hash fallback should recognize
+    // the blob is identical to the one in the index and report nothing changed.
+    #[test]
+    fn test_diff_against_index_racy_mtime_with_unchanged_content_is_not_modified() {
         let entry_name = "simple_file.txt";
         let temp_dir = TempDir::default();
         let mut index = test_repo(&temp_dir, &vec![Path::new(entry_name)]);
         let dir_entries = index.entries.get_mut("").unwrap();
         dir_entries[0].stat.mtime += 1;
+        let value = WorkTree::diff_against_index(&temp_dir, index).unwrap();
+        assert_eq!(value.entries, vec![]);
+    }
+
+    // Simulates the actual "racy git" window: the file is rewritten with different, same-length
+    // content and happens to land on a stat that's identical to what the index already has, at or
+    // after the moment the index itself was written.  The stat-only fast path can't tell this
+    // apart from a genuinely unchanged file by stat alone, so it must fall back to hashing.
+    #[test]
+    fn test_diff_against_index_racy_mtime_equal_to_index_write_time_is_rehashed() {
+        let entry_name = "simple_file.txt";
+        let temp_dir = TempDir::default();
+        let mut index = test_repo(&temp_dir, &vec![Path::new(entry_name)]);
+
+        let file_path = temp_dir.join(entry_name);
+        let original_len = fs::metadata(&file_path).unwrap().len() as usize;
+        let flipped: Vec<u8> = vec![0xffu8; original_len];
+        fs::write(&file_path, flipped).unwrap();
+
+        let on_disk_stat = fs::metadata(&file_path).unwrap();
+        let mtime = on_disk_stat
+            .modified()
+            .unwrap()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dir_entries = index.entries.get_mut("").unwrap();
+        dir_entries[0].stat.mtime = mtime;
+        dir_entries[0].stat.size = on_disk_stat.len() as u32;
+        index.index_mtime = Some(mtime);
+
         let value = WorkTree::diff_against_index(&temp_dir, index).unwrap();
         let entries = vec![StatusEntry {
             name: entry_name.to_string(),
@@ -469,6 +1022,56 @@ mod tests {
         assert_eq!(value.entries, entries);
     }
 
+    // The whole point of the stat-first fast path is that a matching, non-racy stat is trusted
+    // outright and the file is never opened to hash it.  To prove that (rather than just that
+    // the result happens to come out right), this leaves genuinely different content on disk
+    // but makes the index's recorded stat match it exactly, with the index's own write time
+    // safely in the future of the file's mtime so the racy-clean guard doesn't kick in either.
+    #[test]
+    fn test_diff_against_index_trusts_a_non_racy_matching_stat_without_hashing() {
+        let entry_name = "simple_file.txt";
+        let temp_dir = TempDir::default();
+        let mut index = test_repo(&temp_dir, &vec![Path::new(entry_name)]);
+
+        let file_path = temp_dir.join(entry_name);
+        let original_len = fs::metadata(&file_path).unwrap().len() as usize;
+        let flipped: Vec<u8> = vec![0xffu8; original_len];
+        fs::write(&file_path, flipped).unwrap();
+
+        let on_disk_stat = fs::metadata(&file_path).unwrap();
+        let mtime = on_disk_stat
+            .modified()
+            .unwrap()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_nanos();
+        let dir_entries = index.entries.get_mut("").unwrap();
+        dir_entries[0].stat.mtime = mtime;
+        dir_entries[0].stat.size = on_disk_stat.len() as u32;
+        // A full second after the file's mtime is comfortably outside the racy window.
+        index.index_mtime = Some(mtime + 1_000_000_000);
+
+        let value = WorkTree::diff_against_index(&temp_dir, index).unwrap();
+        assert_eq!(value.entries, vec![]);
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_diff_against_index_a_symlink_replacing_a_tracked_file() {
+        let entry_name = "simple_file.txt";
+        let temp_dir = TempDir::default();
+        let index = test_repo(&temp_dir, &vec![Path::new(entry_name)]);
+        let file_path = temp_dir.join(entry_name);
+        fs::remove_file(&file_path).unwrap();
+        std::os::unix::fs::symlink("somewhere/else", &file_path).unwrap();
+        let value = WorkTree::diff_against_index(&temp_dir, index).unwrap();
+        let entries = vec![StatusEntry {
+            name: entry_name.to_string(),
+            state: Status::TypeChange,
+        }];
+        assert_eq!(value.entries, entries);
+    }
+
     #[test]
     fn test_diff_against_index_deeply_nested() {
         let temp_dir = TempDir::default();
@@ -480,9 +1083,12 @@ mod tests {
     #[test]
     fn test_diff_against_modified_index_deeply_nested() {
         let temp_dir = TempDir::default();
-        let mut index = test_repo(&temp_dir, &vec![Path::new("dir_1/dir_2/dir_3/file.txt")]);
-        let dir_entries = index.entries.get_mut("dir_1/dir_2/dir_3").unwrap();
-        dir_entries[0].stat.size += 1;
+        let relative_path = Path::new("dir_1/dir_2/dir_3/file.txt");
+        let index = test_repo(&temp_dir, &vec![relative_path]);
+        let file_path = temp_dir.join(relative_path);
+        let mut contents = fs::read(&file_path).unwrap();
+        contents.extend_from_slice(b"more content");
+        fs::write(&file_path, contents).unwrap();
         let value = WorkTree::diff_against_index(&temp_dir, index).unwrap();
         let entries = vec![StatusEntry {
             name: "dir_1/dir_2/dir_3/file.txt".to_string(),
@@ -532,6 +1138,164 @@ mod tests {
         assert_eq!(value.entries, entries);
     }
 
+    #[test]
+    fn test_diff_against_index_sorts_results_by_path_across_directories() {
+        let temp_dir = TempDir::default();
+        let tracked = vec![Path::new("z_dir/file.txt"), Path::new("a_dir/file.txt")];
+        let index = test_repo(&temp_dir, &tracked);
+
+        // Modify the entry whose directory would be walked last if directories were visited in
+        // the order they were created, so a pass/fail here can't be explained away by lucky
+        // directory-visit ordering.
+        for file in &tracked {
+            fs::write(temp_dir.join(file), "changed").unwrap();
+        }
+
+        let value = WorkTree::diff_against_index(&temp_dir, index).unwrap();
+        let entries = vec![
+            StatusEntry {
+                name: "a_dir/file.txt".to_string(),
+                state: Status::Modified,
+            },
+            StatusEntry {
+                name: "z_dir/file.txt".to_string(),
+                state: Status::Modified,
+            },
+        ];
+        assert_eq!(value.entries, entries);
+    }
+
+    #[test]
+    fn test_diff_against_index_with_worker_count_matches_the_default_pool() {
+        let default_dir = TempDir::default();
+        let index = test_repo(&default_dir, &vec![Path::new("simple_file.txt")]);
+        fs::write(default_dir.join("simple_file.txt"), "changed").unwrap();
+
+        let bounded_dir = TempDir::default();
+        let other_index = test_repo(&bounded_dir, &vec![Path::new("simple_file.txt")]);
+        fs::write(bounded_dir.join("simple_file.txt"), "changed").unwrap();
+
+        let default_pool = WorkTree::diff_against_index(&default_dir, index).unwrap();
+        let bounded_pool =
+            WorkTree::diff_against_index_with_worker_count(&bounded_dir, other_index, 1).unwrap();
+        assert_eq!(bounded_pool.entries, default_pool.entries);
+    }
+
+    #[test]
+    fn test_diff_against_index_streaming_flushes_fixed_size_batches() {
+        let temp_dir = TempDir::default();
+        let index = test_repo(&temp_dir, &vec![Path::new("simple_file.txt")]);
+
+        let new_file_names = vec!["a_file.txt", "b_file.txt", "c_file.txt"];
+        for name in &new_file_names {
+            fs::write(temp_dir.join(name), "stuff").unwrap();
+        }
+
+        let mut batches = vec![];
+        WorkTree::diff_against_index_streaming(
+            &temp_dir,
+            index,
+            1,
+            |batch| batches.push(batch.to_vec()),
+            |_path, _err| panic!("unexpected error"),
+        )
+        .unwrap();
+
+        // Every batch respects the requested size, and every entry is still accounted for.
+        assert!(batches.iter().all(|batch| batch.len() <= 1));
+        let entries: Vec<StatusEntry> = batches.into_iter().flatten().collect();
+        let expected: Vec<StatusEntry> = new_file_names
+            .iter()
+            .map(|&n| StatusEntry {
+                name: n.to_string(),
+                state: Status::New,
+            })
+            .collect();
+        assert_eq!(entries, expected);
+    }
+
+    #[test]
+    fn test_diff_against_index_surfaces_a_broken_submodule_without_failing_the_whole_walk() {
+        let temp_dir = TempDir::default();
+        let sub_dir = TempDir::default();
+        test_repo(&sub_dir, &vec![Path::new("sub_file.txt")]);
+
+        let repo = Repository::init(&temp_dir).unwrap();
+        let mut submodule = repo
+            .submodule(sub_dir.to_str().unwrap(), Path::new("sub_repo_dir"), true)
+            .unwrap();
+        submodule
+            .clone(Some(&mut SubmoduleUpdateOptions::new()))
+            .unwrap();
+        submodule.add_finalize().unwrap();
+
+        let mut index = repo.index().unwrap();
+        index.write().unwrap();
+        let tree_oid = index.write_tree().unwrap();
+        let tree = repo.find_tree(tree_oid).unwrap();
+        let signature = Signature::new("Tucan", "me@me.com", &Time::new(20, 0)).unwrap();
+        repo.commit(
+            Option::from("HEAD"),
+            &signature,
+            &signature,
+            "Adding submodule",
+            &tree,
+            &[],
+        )
+        .unwrap();
+
+        // Simulate a submodule whose `.git` got wiped out from under the walk, e.g. by a
+        // concurrent `git submodule deinit`.
+        fs::remove_dir_all(temp_dir.join("sub_repo_dir").join(".git")).unwrap();
+
+        let index = Index::new(&temp_dir.join(".git/index")).unwrap();
+        let value = WorkTree::diff_against_index(&temp_dir, index).unwrap();
+
+        assert_eq!(value.entries, vec![]);
+        assert_eq!(value.errors.len(), 1);
+        assert_eq!(value.errors[0].0, temp_dir.join("sub_repo_dir"));
+    }
+
+    #[test]
+    fn test_submodule_status_guards_against_runaway_recursion() {
+        let temp_dir = TempDir::default();
+        let dir_entry = ReadDirEntry {
+            name: "sub_repo_dir".to_string(),
+            is_dir: true,
+            is_symlink: false,
+            process: true,
+            stat: FileStat::default(),
+            parent_path: Arc::from(temp_dir.to_path_buf().as_path()),
+            depth: 1,
+        };
+        let index_entry = DirEntry {
+            object_type: ObjectType::GitLink,
+            name: "sub_repo_dir".to_string(),
+            ..Default::default()
+        };
+
+        let (changed_files, _changed_files_receiver) = mpsc::channel();
+        let (errors, error_receiver) = mpsc::channel();
+        let read_dir_state = ReadWorktreeState {
+            path: temp_dir.to_path_buf(),
+            index: Arc::new(Index::default()),
+            changed_files,
+            errors,
+            ignores: vec![],
+            submodule_depth: MAX_SUBMODULE_DEPTH,
+            untracked_mode: UntrackedMode::default(),
+        };
+
+        rayon::scope(|s| {
+            submodule_status(&dir_entry, &index_entry, &read_dir_state, s);
+        });
+        drop(read_dir_state);
+
+        let errors: Vec<(PathBuf, StatusError)> = error_receiver.into_iter().collect();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].0, temp_dir.join("sub_repo_dir"));
+    }
+
     #[test]
     fn test_new_directory_in_worktree_does_not_show() {
         let temp_dir = TempDir::default();
@@ -542,6 +1306,44 @@ mod tests {
         assert_eq!(value.entries, vec![]);
     }
 
+    #[test]
+    fn test_untracked_mode_all_expands_new_directory_into_its_files() {
+        let temp_dir = TempDir::default();
+        let index = test_repo(&temp_dir, &vec![Path::new("simple_file.txt")]);
+        let new_file = temp_dir.join("new_dir/nested/file.txt");
+        fs::create_dir_all(new_file.parent().unwrap()).unwrap();
+        fs::write(&new_file, "stuff").unwrap();
+
+        let value = WorkTree::diff_against_index_with_untracked_mode(
+            &temp_dir,
+            index,
+            UntrackedMode::All,
+        )
+        .unwrap();
+        let entries = vec![StatusEntry {
+            name: "new_dir/nested/file.txt".to_string(),
+            state: Status::New,
+        }];
+        assert_eq!(value.entries, entries);
+    }
+
+    #[test]
+    fn test_untracked_mode_no_hides_new_files_and_directories() {
+        let temp_dir = TempDir::default();
+        let index = test_repo(&temp_dir, &vec![Path::new("simple_file.txt")]);
+        fs::write(temp_dir.join("new_file.txt"), "stuff").unwrap();
+        fs::create_dir_all(temp_dir.join("new_dir")).unwrap();
+        fs::write(temp_dir.join("new_dir/nested.txt"), "stuff").unwrap();
+
+        let value = WorkTree::diff_against_index_with_untracked_mode(
+            &temp_dir,
+            index,
+            UntrackedMode::No,
+        )
+        .unwrap();
+        assert_eq!(value.entries, vec![]);
+    }
+
     #[test]
     fn test_deleted_file_in_worktree() {
         let names = vec!["file_1.txt", "file_2.txt", "foo.txt"];
@@ -616,6 +1418,47 @@ mod tests {
         assert_eq!(value.entries, entries);
     }
 
+    #[test]
+    fn test_diff_against_index_with_fsmonitor_falls_back_without_a_clock() {
+        let temp_dir = TempDir::default();
+        let index = test_repo(&temp_dir, &vec![Path::new("simple_file.txt")]);
+        let new_file = temp_dir.join("new_file.txt");
+        fs::write(&new_file, "stuff").unwrap();
+
+        let (value, state) = WorkTree::diff_against_index_with_fsmonitor(
+            &temp_dir,
+            index,
+            FsmonitorKind::Watchman,
+            FsmonitorState::default(),
+        )
+        .unwrap();
+
+        let entries = vec![StatusEntry {
+            name: "new_file.txt".to_string(),
+            state: Status::New,
+        }];
+        assert_eq!(value.entries, entries);
+        // No clock was available, so none was produced; the caller is expected to obtain one
+        // from the watcher directly before the next run.
+        assert_eq!(state.clock, None);
+    }
+
+    #[test]
+    fn test_diff_against_index_with_fsmonitor_ignores_it_when_not_configured() {
+        let temp_dir = TempDir::default();
+        let index = test_repo(&temp_dir, &vec![Path::new("simple_file.txt")]);
+
+        let (value, _state) = WorkTree::diff_against_index_with_fsmonitor(
+            &temp_dir,
+            index,
+            FsmonitorKind::None,
+            FsmonitorState::default(),
+        )
+        .unwrap();
+
+        assert_eq!(value.entries, vec![]);
+    }
+
     #[test]
     fn test_unignored_files() {
         let seed_names = vec!["simple_file.txt", "foo/.gitignore"];